@@ -1,11 +1,20 @@
-use core::{fmt, str};
+use core::str;
 use std::{
-    fmt::{Debug, Display},
-    hash::Hash,
-    mem::{transmute, ManuallyDrop},
-    ops::Deref,
+    alloc,
+    borrow::Borrow,
+    fmt,
+    mem::ManuallyDrop,
+    ops::{Bound, Deref, RangeBounds},
     ptr,
-    sync::Arc,
+    sync::atomic::Ordering,
+};
+
+use crate::{
+    borrowed::UmbraStr,
+    bytes::UmbraArcBytes,
+    common::{self, impl_umbra_str_traits, UmbraLayout, UmbraStrMut, HEADER_SIZE},
+    sync::{self, AtomicU32, AtomicUsize},
+    UmbraError,
 };
 
 pub const MAX_INLINE: usize = 12;
@@ -20,81 +29,600 @@ pub struct UmbraArcString {
 
 pub union UmbraArcExtra {
     data: [u8; 8],
-    ptr: *const u8,
+    ptr: tag::TaggedPtr,
+}
+
+/// Header at the start of every out-of-line allocation, the string bytes follow directly after it
+#[repr(C)]
+struct ArcHeader {
+    count: AtomicUsize,
+    /// Number of UmbraWeakStrings, plus one held by all the strong references together as in std's Arc. It is set to
+    /// WEAK_LOCKED while checking whether a string is unique
+    weak: AtomicU32,
+    /// Number of bytes in the allocation, which a substring sharing it or a string taken over from an UmbraBoxString
+    /// with spare capacity is shorter than, but may have to free it with
+    len: u32,
+}
+
+const WEAK_LOCKED: u32 = u32::MAX;
+
+const _: () = assert!(size_of::<ArcHeader>() == HEADER_SIZE);
+
+/// Tagging ptr with its StorageClass and a substring's offset, in the spare top bits of a 64 bit pointer
+#[cfg(target_pointer_width = "64")]
+mod tag {
+    use super::StorageClass;
+
+    /// An out-of-line string's pointer along with its StorageClass and, for a slice, its offset
+    pub(crate) type TaggedPtr = *const u8;
+
+    /// The top two bits of ptr hold the StorageClass of an out-of-line string. The top of the address space belongs to
+    /// the kernel on the 64 bit platforms we support, so these bits are otherwise unused
+    const CLASS_MASK: usize = 0b11 << (usize::BITS - 2);
+    /// Set on ptr when it points straight at the bytes of a &'static str rather than at a refcounted allocation
+    const PERSISTENT_TAG: usize = 0b10 << (usize::BITS - 2);
+    /// Set on ptr when it points straight at bytes borrowed from a buffer which will go away, such as a page
+    const TRANSIENT_TAG: usize = 0b01 << (usize::BITS - 2);
+    /// Set on ptr when it points at a refcounted allocation shared with a longer string, with the offset of the bytes
+    /// in it held in the bits between the address and the tag
+    const SLICE_TAG: usize = 0b11 << (usize::BITS - 2);
+    /// Addresses fit in the low 48 bits on the platforms we support, so a slice's offset goes above them
+    const OFFSET_SHIFT: u32 = 48;
+    const ADDR_MASK: usize = (1 << OFFSET_SHIFT) - 1;
+    /// Largest offset a slice can hold, substrings starting further into the allocation are copied instead
+    const MAX_SLICE_OFFSET: usize = (1 << (usize::BITS - 2 - OFFSET_SHIFT)) - 1;
+
+    pub(super) const fn persistent(bytes: *const u8) -> TaggedPtr {
+        bytes.wrapping_byte_add(PERSISTENT_TAG)
+    }
+
+    pub(super) fn transient(bytes: *const u8) -> TaggedPtr {
+        bytes.map_addr(|addr| addr | TRANSIENT_TAG)
+    }
+
+    pub(super) fn refcounted(header: *const u8) -> TaggedPtr {
+        header
+    }
+
+    pub(super) fn storage_class(ptr: TaggedPtr) -> StorageClass {
+        match ptr.addr() & CLASS_MASK {
+            0 | SLICE_TAG => StorageClass::Temporary,
+            PERSISTENT_TAG => StorageClass::Persistent,
+            TRANSIENT_TAG => StorageClass::Transient,
+            _ => unreachable!("invalid storage class tag"),
+        }
+    }
+
+    pub(super) fn is_slice(ptr: TaggedPtr) -> bool {
+        ptr.addr() & CLASS_MASK == SLICE_TAG
+    }
+
+    /// The bytes a persistent or transient ptr points at
+    pub(super) fn untag(ptr: TaggedPtr) -> *const u8 {
+        ptr.map_addr(|addr| addr & !CLASS_MASK)
+    }
+
+    /// Tags the bytes of a persistent or transient string with the tag of another
+    pub(super) fn retag(bytes: *const u8, tagged: TaggedPtr) -> TaggedPtr {
+        bytes.map_addr(|addr| addr | tagged.addr() & CLASS_MASK)
+    }
+
+    /// The allocation a refcounted ptr points into and the offset of the string's bytes in it
+    pub(super) fn slice_parts(ptr: TaggedPtr) -> (*const u8, usize) {
+        if is_slice(ptr) {
+            let offset = (ptr.addr() >> OFFSET_SHIFT) & MAX_SLICE_OFFSET;
+            (ptr.map_addr(|addr| addr & ADDR_MASK), offset)
+        } else {
+            (ptr, 0)
+        }
+    }
+
+    /// A slice of the allocation at base, or None if offset or the address don't fit
+    pub(super) fn slice(base: *const u8, offset: usize) -> Option<TaggedPtr> {
+        if offset > MAX_SLICE_OFFSET || base.addr() > ADDR_MASK {
+            return None;
+        }
+        Some(base.map_addr(|addr| addr | offset << OFFSET_SHIFT | SLICE_TAG))
+    }
+}
+
+/// Without spare pointer bits the StorageClass and a slice's offset go in a word of their own, which fits in the 8
+/// bytes of extra next to a pointer narrower than 64 bits
+#[cfg(not(target_pointer_width = "64"))]
+mod tag {
+    use super::StorageClass;
+
+    /// An out-of-line string's pointer along with its StorageClass and, for a slice, its offset
+    #[repr(C)]
+    #[derive(Clone, Copy)]
+    pub(crate) struct TaggedPtr {
+        ptr: *const u8,
+        /// The StorageClass in the low two bits, and a slice's offset above them
+        tag: usize,
+    }
+
+    const _: () = assert!(size_of::<TaggedPtr>() <= size_of::<[u8; 8]>());
+
+    const CLASS_MASK: usize = 0b11;
+    const PERSISTENT_TAG: usize = 0b10;
+    const TRANSIENT_TAG: usize = 0b01;
+    const SLICE_TAG: usize = 0b11;
+    const OFFSET_SHIFT: u32 = 2;
+    const MAX_SLICE_OFFSET: usize = usize::MAX >> OFFSET_SHIFT;
+
+    pub(super) const fn persistent(bytes: *const u8) -> TaggedPtr {
+        TaggedPtr {
+            ptr: bytes,
+            tag: PERSISTENT_TAG,
+        }
+    }
+
+    pub(super) fn transient(bytes: *const u8) -> TaggedPtr {
+        TaggedPtr {
+            ptr: bytes,
+            tag: TRANSIENT_TAG,
+        }
+    }
+
+    pub(super) fn refcounted(header: *const u8) -> TaggedPtr {
+        TaggedPtr {
+            ptr: header,
+            tag: 0,
+        }
+    }
+
+    pub(super) fn storage_class(ptr: TaggedPtr) -> StorageClass {
+        match ptr.tag & CLASS_MASK {
+            0 | SLICE_TAG => StorageClass::Temporary,
+            PERSISTENT_TAG => StorageClass::Persistent,
+            TRANSIENT_TAG => StorageClass::Transient,
+            _ => unreachable!("invalid storage class tag"),
+        }
+    }
+
+    pub(super) fn is_slice(ptr: TaggedPtr) -> bool {
+        ptr.tag & CLASS_MASK == SLICE_TAG
+    }
+
+    /// The bytes a persistent or transient ptr points at
+    pub(super) fn untag(ptr: TaggedPtr) -> *const u8 {
+        ptr.ptr
+    }
+
+    /// Tags the bytes of a persistent or transient string with the tag of another
+    pub(super) fn retag(bytes: *const u8, tagged: TaggedPtr) -> TaggedPtr {
+        TaggedPtr {
+            ptr: bytes,
+            tag: tagged.tag,
+        }
+    }
+
+    /// The allocation a refcounted ptr points into and the offset of the string's bytes in it
+    pub(super) fn slice_parts(ptr: TaggedPtr) -> (*const u8, usize) {
+        (ptr.ptr, ptr.tag >> OFFSET_SHIFT)
+    }
+
+    /// A slice of the allocation at base, or None if offset doesn't fit
+    pub(super) fn slice(base: *const u8, offset: usize) -> Option<TaggedPtr> {
+        if offset > MAX_SLICE_OFFSET {
+            return None;
+        }
+        Some(TaggedPtr {
+            ptr: base,
+            tag: offset << OFFSET_SHIFT | SLICE_TAG,
+        })
+    }
+}
+
+/// Where the bytes of an Umbra string live and how long they are valid for, following the storage classes of the Umbra
+/// paper
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum StorageClass {
+    /// Valid for the rest of the program, such as a string literal
+    Persistent,
+    /// Borrowed from a buffer which may go away, such as a page in the buffer manager, and must be promoted to be kept
+    Transient,
+    /// Owned by the string itself, either inline or in a reference counted allocation
+    Temporary,
 }
 
+// SAFETY: UmbraArcString behaves like Arc<str>. Inline strings are plain bytes. The bytes of a refcounted allocation
+// are only written through &mut self once is_unique has shown no other strong or weak reference can reach them, and
+// its counts are atomic with the orderings of Arc, so the drop which frees it happens after every other reference on
+// any thread is done with it. Persistent strings borrow a &'static str, and transient ones a str which from_transient's
+// contract keeps alive until every clone is dropped, wherever that is
+unsafe impl Send for UmbraArcString {}
+// SAFETY: as above, &UmbraArcString only gives out shared access to the bytes, and clone only touches the atomic count
+unsafe impl Sync for UmbraArcString {}
+
 impl UmbraArcString {
+    /// Panics if the string is longer than u32::MAX bytes
     pub fn new(val: impl AsRef<str>) -> UmbraArcString {
-        let val_str = val.as_ref();
-
-        // TODO: should I check for overflow here?
-        let len = val_str.len();
+        common::unwrap_new(Self::try_new(val))
+    }
 
-        if len <= MAX_INLINE {
-            eprintln!("inlining!!!");
-            let mut inline: [u8; 12] = [0; 12];
-            inline[..len].copy_from_slice(val_str.as_bytes());
-            // SAFETY: inline is of length 12 and align 1, and it is being split into arrays of length 4 and 8
-            let (prefix, extra): ([u8; 4], [u8; 8]) = unsafe { transmute(inline) };
+    pub fn try_new(val: impl AsRef<str>) -> Result<UmbraArcString, UmbraError> {
+        let val_str = val.as_ref();
+        let len = common::checked_len(val_str.len())?;
 
-            eprintln!("extra: {}", String::from_utf8(extra.to_vec()).unwrap());
+        if len as usize <= MAX_INLINE {
+            let (prefix, extra) = common::split_inline(val_str.as_bytes());
 
-            UmbraArcString {
-                len: len as u32,
+            Ok(UmbraArcString {
+                len,
                 prefix,
                 extra: UmbraArcExtra { data: extra },
-            }
+            })
         } else {
-            let mut prefix = [0; 4];
-            prefix.copy_from_slice(&val_str.as_bytes()[0..4]);
+            Ok(UmbraArcString {
+                len,
+                prefix: common::heap_prefix(val_str.as_bytes()),
+                extra: UmbraArcExtra::try_inner_ptr_new(val_str.as_bytes())?,
+            })
+        }
+    }
 
-            UmbraArcString {
-                len: len as u32,
-                prefix,
-                extra: UmbraArcExtra::inner_ptr_new(val_str),
+    /// Wraps a string literal without copying it, clone and drop of the result never touch a refcount
+    ///
+    /// Panics if the string is longer than u32::MAX bytes
+    pub const fn from_static(val: &'static str) -> UmbraArcString {
+        let bytes = val.as_bytes();
+        assert!(
+            bytes.len() <= u32::MAX as usize,
+            "string longer than u32::MAX"
+        );
+        let len = bytes.len() as u32;
+
+        let mut inline = [0; 12];
+        let mut i = 0;
+        while i < bytes.len() && i < MAX_INLINE {
+            inline[i] = bytes[i];
+            i += 1;
+        }
+        let prefix = [inline[0], inline[1], inline[2], inline[3]];
+
+        let extra = if len as usize <= MAX_INLINE {
+            let [_, _, _, _, data @ ..] = inline;
+            UmbraArcExtra { data }
+        } else {
+            UmbraArcExtra {
+                ptr: tag::persistent(bytes.as_ptr()),
             }
+        };
+
+        UmbraArcString { len, prefix, extra }
+    }
+
+    /// Wraps a string without copying it, the result is transient so clone and drop never touch a refcount
+    ///
+    /// Panics if the string is longer than u32::MAX bytes
+    ///
+    /// # Safety
+    ///
+    /// val must stay valid and unchanged until the result and every clone of it are dropped, promote should be used to
+    /// get a string which can outlive it
+    pub unsafe fn from_transient(val: &str) -> UmbraArcString {
+        let len = common::unwrap_new(common::checked_len(val.len()));
+
+        if len as usize <= MAX_INLINE {
+            return UmbraArcString::new(val);
+        }
+
+        UmbraArcString {
+            len,
+            prefix: common::heap_prefix(val.as_bytes()),
+            extra: UmbraArcExtra {
+                ptr: tag::transient(val.as_ptr()),
+            },
+        }
+    }
+
+    /// Takes ownership of an out-of-line allocation from one of the other Umbra string types
+    ///
+    /// SAFETY: ptr must be from common::heap_alloc with room for cap bytes after the header, the first len of which are
+    /// valid utf-8, len must be over MAX_INLINE, prefix must be its first 4 bytes, and nothing else may reference the
+    /// allocation
+    pub(crate) unsafe fn from_unique_heap(
+        len: u32,
+        prefix: [u8; 4],
+        ptr: *mut u8,
+        cap: u32,
+    ) -> Self {
+        debug_assert!(len as usize > MAX_INLINE && len <= cap);
+        // SAFETY: ptr points to an allocation we now own, with room for the header
+        unsafe {
+            ptr.cast::<ArcHeader>().write(ArcHeader {
+                count: AtomicUsize::new(1),
+                weak: AtomicU32::new(1),
+                len: cap,
+            })
+        };
+
+        UmbraArcString {
+            len,
+            prefix,
+            extra: UmbraArcExtra {
+                ptr: tag::refcounted(ptr),
+            },
+        }
+    }
+
+    /// Gives up the out-of-line allocation if this is the only reference to it and it holds exactly the string's bytes,
+    /// so another Umbra string type can take it over
+    pub(crate) fn try_into_unique_heap(self) -> Result<*mut u8, Self> {
+        debug_assert!(!self.is_inline());
+        // SAFETY: !is_inline() so ptr is active
+        if unsafe { self.extra.is_slice() } || !self.is_unique() {
+            return Err(self);
+        }
+        // SAFETY: is_unique() so ptr is active and refcounted
+        if unsafe { self.extra.inner_ptr_header() }.len != self.len {
+            return Err(self);
+        }
+
+        let this = ManuallyDrop::new(self);
+        // SAFETY: !is_inline() so ptr is active and refcounted, and this no longer drops its reference
+        Ok(unsafe { this.extra.inner_ptr_parts() }.0.cast_mut())
+    }
+
+    /// A borrowed view of this string, with the same layout
+    pub fn as_umbra_str(&self) -> UmbraStr<'_> {
+        if self.is_inline() {
+            // SAFETY: is_inline() so data is active, and holds the rest of the string after the prefix
+            unsafe { UmbraStr::from_inline_parts(self.len, self.prefix, self.extra.data) }
+        } else {
+            // SAFETY: !is_inline() so ptr is active, the bytes live as long as self
+            let bytes = unsafe { self.extra.inner_ptr_bytes(self.len) };
+            // SAFETY: bytes are the whole string which starts with prefix
+            unsafe { UmbraStr::from_ptr_parts(self.len, self.prefix, bytes.as_ptr()) }
         }
     }
 
+    /// SAFETY: the parts must be taken from an UmbraArcString or from another type using UmbraArcExtra the same
+    /// way, whose contents are valid utf-8
+    pub(crate) unsafe fn from_raw_parts(len: u32, prefix: [u8; 4], extra: UmbraArcExtra) -> Self {
+        UmbraArcString { len, prefix, extra }
+    }
+
+    /// Takes the parts out without dropping the reference held in extra
+    pub(crate) fn into_raw_parts(self) -> (u32, [u8; 4], UmbraArcExtra) {
+        let this = ManuallyDrop::new(self);
+        // SAFETY: this is never used or dropped again, so extra is moved out exactly once
+        (this.len, this.prefix, unsafe { ptr::read(&this.extra) })
+    }
+
+    /// The two 8 byte words of the layout, the first holding len and prefix and the second the inline data or ptr
+    #[cfg(target_pointer_width = "64")]
+    pub(crate) fn raw_words(&self) -> (u64, *mut u8) {
+        let head = u64::from(self.len) | u64::from(u32::from_ne_bytes(self.prefix)) << 32;
+        let extra = if self.is_inline() {
+            // SAFETY: is_inline() so data is active
+            ptr::without_provenance_mut(usize::from_ne_bytes(unsafe { self.extra.data }))
+        } else {
+            // SAFETY: !is_inline() so ptr is active
+            unsafe { self.extra.ptr }.cast_mut()
+        };
+        (head, extra)
+    }
+
+    /// Converts into a byte string without copying
+    pub fn into_bytes(self) -> UmbraArcBytes {
+        self.into()
+    }
+
     pub fn is_inline(&self) -> bool {
         self.len <= MAX_INLINE as u32
     }
 
+    /// Whether this borrows a &'static str from from_static, rather than owning a refcounted allocation
+    pub fn is_static(&self) -> bool {
+        self.storage_class() == StorageClass::Persistent
+    }
+
+    pub fn storage_class(&self) -> StorageClass {
+        if self.is_inline() {
+            StorageClass::Temporary
+        } else {
+            // SAFETY: !is_inline() so ptr is active
+            unsafe { self.extra.storage_class() }
+        }
+    }
+
+    /// Copies a transient string into an allocation of its own so it can outlive the buffer it borrows from, other
+    /// strings are returned as they are
+    pub fn promote(self) -> UmbraArcString {
+        match self.storage_class() {
+            StorageClass::Transient => UmbraArcString::new(&*self),
+            StorageClass::Persistent | StorageClass::Temporary => self,
+        }
+    }
+
+    /// The bytes in range as a string of their own. A substring too long to inline shares this string's allocation
+    /// rather than copying it, and is persistent or transient if this is
+    ///
+    /// On 64 bit targets a refcounted substring keeps its offset into the allocation in the spare bits of the pointer,
+    /// so one starting more than 16383 bytes in is copied to an allocation of its own instead.
+    ///
+    /// Panics if the range is out of bounds or doesn't fall on char boundaries, like indexing a str
+    pub fn substring(&self, range: impl RangeBounds<usize>) -> UmbraArcString {
+        let range: (Bound<usize>, Bound<usize>) =
+            (range.start_bound().cloned(), range.end_bound().cloned());
+        let val = &(**self)[range];
+        let start = val.as_ptr().addr() - self.as_ptr().addr();
+        if val.len() <= MAX_INLINE {
+            return UmbraArcString::new(val);
+        }
+
+        // SAFETY: val is longer than this can inline, so neither is inline and ptr is active
+        let extra = match unsafe { self.extra.slice(start) } {
+            Some(extra) => extra,
+            None => return UmbraArcString::new(val),
+        };
+        UmbraArcString {
+            len: val.len() as u32,
+            prefix: common::heap_prefix(val.as_bytes()),
+            extra,
+        }
+    }
+
+    /// Whether nothing else can see this string's bytes, so they can be changed in place. A weak reference could
+    /// upgrade and see them, so there can't be any
+    fn is_unique(&self) -> bool {
+        if self.is_inline() {
+            return true;
+        }
+        if self.storage_class() != StorageClass::Temporary {
+            return false;
+        }
+
+        // SAFETY: !is_inline() so ptr is active, and it is refcounted
+        let header = unsafe { self.extra.inner_ptr_header() };
+        // Locking the weak count stops another strong reference downgrading between checking it and the strong count,
+        // as in Arc::get_mut. Acquire pairs with the Release decrements of dropped references, so they are done with
+        // the bytes
+        if header
+            .weak
+            .compare_exchange(1, WEAK_LOCKED, Ordering::Acquire, Ordering::Relaxed)
+            .is_err()
+        {
+            return false;
+        }
+        let unique = header.count.load(Ordering::Acquire) == 1;
+        header.weak.store(1, Ordering::Release);
+        unique
+    }
+
+    /// Mutable access to the contents if nothing else shares them, the prefix is updated when the returned guard is
+    /// dropped. Persistent and transient strings borrow their bytes so never give access
+    pub fn get_mut(&mut self) -> Option<UmbraStrMut<'_>> {
+        if self.is_unique() {
+            // SAFETY: is_unique() was just checked
+            Some(unsafe { self.as_mut_str_unchecked() })
+        } else {
+            None
+        }
+    }
+
+    /// Mutable access to the contents, copying them first if they are shared so other strings don't see the change
+    pub fn make_mut(&mut self) -> UmbraStrMut<'_> {
+        if !self.is_unique() {
+            *self = UmbraArcString::new(&**self);
+        }
+        // SAFETY: self is either unique already or a fresh copy
+        unsafe { self.as_mut_str_unchecked() }
+    }
+
+    /// SAFETY: is_unique() must hold
+    unsafe fn as_mut_str_unchecked(&mut self) -> UmbraStrMut<'_> {
+        let len = self.len;
+        if self.is_inline() {
+            // SAFETY: UmbraArcString has the Umbra layout and is_inline() so data is active
+            let bytes = unsafe { common::inline_bytes_mut(self, len) };
+            // SAFETY: bytes were copied from a str, so should be valid utf-8
+            UmbraStrMut::new(None, unsafe { str::from_utf8_unchecked_mut(bytes) })
+        } else {
+            // SAFETY: !is_inline() so ptr is active, and under preconditions this is the only reference to the
+            // refcounted allocation, whose bytes don't overlap prefix
+            let bytes = unsafe { self.extra.inner_ptr_bytes_mut(len) };
+            // SAFETY: bytes were copied from a str, so should be valid utf-8
+            UmbraStrMut::new(Some(&mut self.prefix), unsafe {
+                str::from_utf8_unchecked_mut(bytes)
+            })
+        }
+    }
+
+    /// The contents as a String if nothing else shares them, otherwise gives the string back
+    pub fn try_unwrap(self) -> Result<String, Self> {
+        if self.is_unique() {
+            Ok(String::from(&*self))
+        } else {
+            Err(self)
+        }
+    }
+
+    /// A weak reference to this string, which doesn't keep the bytes alive
+    pub fn downgrade(&self) -> UmbraWeakString {
+        let extra = if self.is_inline() {
+            // SAFETY: is_inline() so data is active
+            unsafe { self.extra.inner_data_clone() }
+        } else {
+            // SAFETY: !is_inline() so ptr is active
+            unsafe { self.extra.inner_ptr_downgrade() }
+        };
+        UmbraWeakString {
+            len: self.len,
+            prefix: self.prefix,
+            extra: ManuallyDrop::new(extra),
+        }
+    }
+
+    /// The number of strings sharing this one's allocation, None if it isn't refcounted
+    pub fn strong_count(&self) -> Option<usize> {
+        if self.is_inline() {
+            return None;
+        }
+        // SAFETY: !is_inline() so ptr is active
+        unsafe { self.extra.strong_count() }
+    }
+
+    /// The number of weak references to this string's allocation, None if it isn't refcounted
+    pub fn weak_count(&self) -> Option<usize> {
+        if self.is_inline() {
+            return None;
+        }
+        // SAFETY: !is_inline() so ptr is active
+        unsafe { self.extra.weak_count() }
+    }
+
     pub fn len(&self) -> usize {
         self.len as usize
     }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
 }
 
-impl UmbraArcString {
+impl UmbraLayout for UmbraArcString {
     #[inline]
-    fn suffix_bytes(&self) -> &[u8] {
+    fn raw_len(&self) -> u32 {
+        self.len
+    }
+
+    #[inline]
+    fn raw_prefix(&self) -> [u8; 4] {
+        self.prefix
+    }
+
+    #[inline]
+    fn inline_data(&self) -> Option<&[u8; 8]> {
         if self.is_inline() {
-            // SAFETY: is_inline() so data is valid
-            unsafe { &self.extra.data }
+            // SAFETY: is_inline() so data is active
+            Some(unsafe { &self.extra.data })
         } else {
-            // SAFETY: is_inline() so ptr is valid
-            let s = unsafe { &*self.extra.inner_ptr_to_arc(self.len) };
-            let tmp_slice = &s.as_bytes()[4..];
-
-            // SAFETY: data is valid for as long as UmbraArcString is
-            unsafe { transmute(tmp_slice) }
+            None
         }
     }
+
+    #[inline]
+    fn suffix_bytes(&self) -> &[u8] {
+        self.as_bytes().get(4..).unwrap_or_default()
+    }
 }
 
 impl Clone for UmbraArcString {
     fn clone(&self) -> Self {
         if self.is_inline() {
             Self {
-                len: self.len.clone(),
-                prefix: self.prefix.clone(),
+                len: self.len,
+                prefix: self.prefix,
                 // SAFETY: is_inline() so data is active
                 extra: unsafe { self.extra.inner_data_clone() },
             }
         } else {
             Self {
-                len: self.len.clone(),
-                prefix: self.prefix.clone(),
+                len: self.len,
+                prefix: self.prefix,
                 // SAFETY: !is_inline() so ptr is active
                 extra: unsafe { self.extra.inner_ptr_clone() },
             }
@@ -102,173 +630,445 @@ impl Clone for UmbraArcString {
     }
 }
 
-impl AsRef<str> for UmbraArcString {
-    fn as_ref(&self) -> &str {
-        &**self
-    }
-}
-
 impl Deref for UmbraArcString {
     type Target = str;
 
     fn deref(&self) -> &Self::Target {
-        if self.is_inline() {
-            // SAFETY: following 8 bytes are extra and data is active as is_inline()
-            let byte_arr: &[u8; 12] = unsafe { transmute(&self.prefix) };
-            // SAFETY: bytes were taken from str::as_bytes, so should be valid utf-8
-            unsafe { str::from_utf8_unchecked(&byte_arr[..self.len as usize]) }
+        let bytes = if self.is_inline() {
+            // SAFETY: UmbraArcString has the Umbra layout and is_inline() so data is active
+            unsafe { common::inline_bytes(self, self.len) }
         } else {
             // SAFETY: !is_inline() so ptr is active
-            let tmp_ref: &str = unsafe { &*self.extra.inner_ptr_to_arc(self.len) };
-            // SAFETY: this memory is valid as long as the UmbraArcString is valid
-            unsafe { transmute(tmp_ref) }
-        }
+            unsafe { self.extra.inner_ptr_bytes(self.len) }
+        };
+        // SAFETY: bytes were copied from a str, so should be valid utf-8
+        unsafe { str::from_utf8_unchecked(bytes) }
     }
 }
 
-impl Display for UmbraArcString {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        fmt::Display::fmt(&**self, f)
-    }
-}
+impl_umbra_str_traits!(UmbraArcString);
 
-impl Debug for UmbraArcString {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        fmt::Debug::fmt(&**self, f)
+impl Borrow<str> for UmbraArcString {
+    fn borrow(&self) -> &str {
+        self
     }
 }
 
-impl Hash for UmbraArcString {
-    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
-        (**self).hash(state)
+impl Drop for UmbraArcString {
+    fn drop(&mut self) {
+        if !self.is_inline() {
+            // SAFETY: !is_inline() so ptr is active, ptr is private and created with inner_ptr_new
+            unsafe { self.extra.inner_ptr_drop() }
+        }
     }
 }
 
-impl Eq for UmbraArcString {}
+/// A weak reference to an UmbraArcString, which doesn't keep its allocation's bytes alive but can be upgraded back to
+/// the string while some other reference does
+///
+/// Inline, persistent and transient strings hold no refcount so always upgrade, as with from_transient the buffer a
+/// transient string borrows from must outlive its weak references too.
+#[repr(C)]
+pub struct UmbraWeakString {
+    len: u32,
+    prefix: [u8; 4],
+    /// Holds a weak reference when refcounted, so never dropped as a strong one
+    extra: ManuallyDrop<UmbraArcExtra>,
+}
 
-impl PartialEq<UmbraArcString> for UmbraArcString {
-    fn eq(&self, other: &UmbraArcString) -> bool {
-        let self_len_prefix = ptr::from_ref(self).cast::<u64>();
-        let other_len_prefix = ptr::from_ref(other).cast::<u64>();
-        // SAFETY: both are valid references and UmbraArcString has 8byte alignment so the reads are aligned
-        if unsafe { *self_len_prefix != *other_len_prefix } {
-            return false;
-        }
+// SAFETY: like Weak<str>, a weak reference only touches the atomic counts until it upgrades to an UmbraArcString,
+// which is Send and Sync
+unsafe impl Send for UmbraWeakString {}
+// SAFETY: as above
+unsafe impl Sync for UmbraWeakString {}
 
-        if self.is_inline() && other.is_inline() {
-            // SAFETY: both are inline so data is active
-            unsafe { self.extra.data == other.extra.data }
+impl UmbraWeakString {
+    /// The string, if it hasn't been dropped since this was created
+    pub fn upgrade(&self) -> Option<UmbraArcString> {
+        let extra = if self.is_inline() {
+            // SAFETY: is_inline() so data is active
+            unsafe { self.extra.inner_data_clone() }
         } else {
-            self.suffix_bytes() == self.suffix_bytes()
-        }
+            // SAFETY: !is_inline() so ptr is active and holds a weak reference
+            unsafe { self.extra.inner_ptr_upgrade()? }
+        };
+        Some(UmbraArcString {
+            len: self.len,
+            prefix: self.prefix,
+            extra,
+        })
     }
-}
 
-impl PartialEq<&str> for UmbraArcString {
-    fn eq(&self, other: &&str) -> bool {
-        self.as_ref() == *other
+    fn is_inline(&self) -> bool {
+        self.len <= MAX_INLINE as u32
     }
-}
 
-impl Ord for UmbraArcString {
-    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
-        match self.prefix.cmp(&other.prefix) {
-            std::cmp::Ordering::Less => std::cmp::Ordering::Less,
-            std::cmp::Ordering::Equal => {
-                if self.len <= 4 && other.len <= 4 {
-                    std::cmp::Ordering::Equal
-                } else if self.is_inline() && other.is_inline() {
-                    let ordering = unsafe { self.extra.data.cmp(&other.extra.data) };
-                    ordering.then_with(|| self.len.cmp(&other.len))
-                } else {
-                    self.suffix_bytes().cmp(other.suffix_bytes())
-                }
-            }
-            std::cmp::Ordering::Greater => std::cmp::Ordering::Greater,
+    /// The number of strings sharing the allocation, which is 0 once they have all been dropped. None if it isn't
+    /// refcounted
+    pub fn strong_count(&self) -> Option<usize> {
+        if self.is_inline() {
+            return None;
+        }
+        // SAFETY: !is_inline() so ptr is active, and the weak reference keeps the header alive
+        unsafe { self.extra.strong_count() }
+    }
+
+    /// The number of weak references to the allocation, None if it isn't refcounted
+    pub fn weak_count(&self) -> Option<usize> {
+        if self.is_inline() {
+            return None;
         }
+        // SAFETY: !is_inline() so ptr is active, and the weak reference keeps the header alive
+        unsafe { self.extra.weak_count() }
     }
 }
 
-impl PartialOrd<UmbraArcString> for UmbraArcString {
-    fn partial_cmp(&self, other: &UmbraArcString) -> Option<std::cmp::Ordering> {
-        Some(self.cmp(other))
+impl Clone for UmbraWeakString {
+    fn clone(&self) -> Self {
+        let extra = if self.is_inline() {
+            // SAFETY: is_inline() so data is active
+            unsafe { self.extra.inner_data_clone() }
+        } else {
+            // SAFETY: !is_inline() so ptr is active and holds a weak reference
+            unsafe { self.extra.inner_ptr_weak_clone() }
+        };
+        UmbraWeakString {
+            len: self.len,
+            prefix: self.prefix,
+            extra: ManuallyDrop::new(extra),
+        }
     }
 }
 
-impl PartialOrd<&str> for UmbraArcString {
-    fn partial_cmp(&self, other: &&str) -> Option<std::cmp::Ordering> {
-        Some(self.as_ref().cmp(other))
+impl fmt::Debug for UmbraWeakString {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("(Weak)")
     }
 }
 
-impl Drop for UmbraArcString {
+impl Drop for UmbraWeakString {
     fn drop(&mut self) {
         if !self.is_inline() {
-            // SAFETY: !is_inline() so ptr is active, ptr is private and created with Arc::into_raw
-            unsafe { self.extra.inner_ptr_drop() }
+            // SAFETY: !is_inline() so ptr is active and holds a weak reference
+            unsafe { self.extra.inner_ptr_weak_drop() }
         }
     }
 }
 
 impl UmbraArcExtra {
-    fn inner_ptr_new(val: &str) -> Self {
-        let stored: Arc<str> = Arc::from(val);
-        let str_ptr = Arc::into_raw(stored);
-        let byte_slice = (unsafe { &*str_ptr }).as_bytes();
-        let ptr = byte_slice.as_ptr();
-        Self { ptr }
+    pub(crate) fn inline(data: [u8; 8]) -> Self {
+        Self { data }
     }
 
-    /// SAFETY: Must be called with ptr field and with the value returned from inner_ptr_new, and with the length of the string it was called with
-    unsafe fn inner_ptr_to_arc(&self, len: u32) -> ManuallyDrop<Arc<str>> {
+    /// SAFETY: Must be called with data field active
+    pub(crate) unsafe fn inline_data(&self) -> &[u8; 8] {
+        // SAFETY: data must be active under preconditions
+        unsafe { &self.data }
+    }
+
+    pub(crate) fn try_inner_ptr_new(val: &[u8]) -> Result<Self, UmbraError> {
+        let ptr = common::try_heap_alloc(val.len())?;
+
+        // SAFETY: ptr was just allocated with room for the header followed by val.len() bytes
+        unsafe {
+            ptr.cast::<ArcHeader>().write(ArcHeader {
+                count: AtomicUsize::new(1),
+                weak: AtomicU32::new(1),
+                len: val.len() as u32,
+            });
+            ptr::copy_nonoverlapping(val.as_ptr(), ptr.add(HEADER_SIZE), val.len());
+        }
+
+        Ok(Self {
+            ptr: tag::refcounted(ptr),
+        })
+    }
+
+    /// SAFETY: Must be called with ptr field active
+    unsafe fn storage_class(&self) -> StorageClass {
         // SAFETY: ptr must be active under preconditions
-        let ptr = self.ptr;
-        let byte_slice = ptr::slice_from_raw_parts(ptr, len as usize);
-        // SAFETY: same ptr and length
-        let str_ptr = unsafe { str::from_utf8_unchecked(&*byte_slice) };
-        let str_arc = unsafe { Arc::from_raw(str_ptr) };
+        tag::storage_class(unsafe { self.ptr })
+    }
 
-        ManuallyDrop::new(str_arc)
+    /// SAFETY: Must be called with ptr field active
+    unsafe fn is_refcounted(&self) -> bool {
+        // SAFETY: ptr must be active under preconditions
+        (unsafe { self.storage_class() }) == StorageClass::Temporary
     }
 
-    /// SAFETY: Must be called with ptr field active and it containing a pointer from Arc::into_raw
-    unsafe fn inner_ptr_clone(&self) -> Self {
+    /// SAFETY: Must be called with ptr field active
+    unsafe fn is_slice(&self) -> bool {
         // SAFETY: ptr must be active under preconditions
-        let arc_raw = unsafe { self.ptr };
+        tag::is_slice(unsafe { self.ptr })
+    }
 
-        // SAFETY: ptr must have a pointer from Arc::into_raw
-        let old_arc = unsafe { Arc::from_raw(arc_raw) };
-        let new_arc = old_arc.clone();
+    /// The allocation a refcounted ptr points into and the offset of the string's bytes in it
+    ///
+    /// SAFETY: Must be called with ptr field active and refcounted
+    unsafe fn inner_ptr_parts(&self) -> (*const u8, usize) {
+        // SAFETY: ptr must be active under preconditions
+        tag::slice_parts(unsafe { self.ptr })
+    }
 
-        // prevent dropping of old from decrementing ref count
-        let _ = Arc::into_raw(old_arc);
+    /// Another reference to the bytes from offset onwards, or None if they can't be shared and must be copied
+    ///
+    /// SAFETY: Must be called with ptr field active, and offset within the bytes
+    unsafe fn slice(&self, offset: usize) -> Option<Self> {
+        // SAFETY: ptr must be active under preconditions
+        let ptr = unsafe { self.ptr };
+        // SAFETY: ptr must be active under preconditions
+        if !unsafe { self.is_refcounted() } {
+            // SAFETY: offset is within the bytes under preconditions, so the result stays in the same string
+            let bytes = unsafe { tag::untag(ptr).add(offset) };
+            return Some(UmbraArcExtra {
+                ptr: tag::retag(bytes, ptr),
+            });
+        }
 
-        UmbraArcExtra {
-            ptr: Arc::into_raw(new_arc),
+        // SAFETY: ptr is active and refcounted
+        let (base, base_offset) = unsafe { self.inner_ptr_parts() };
+        let sliced = tag::slice(base, base_offset + offset)?;
+
+        // SAFETY: ptr is active, the reference the clone takes is handed over to the slice
+        let _ = unsafe { self.inner_ptr_clone() };
+        Some(UmbraArcExtra { ptr: sliced })
+    }
+
+    /// SAFETY: Must be called with ptr field active and with the length of the string it was created with
+    pub(crate) unsafe fn inner_ptr_bytes(&self, len: u32) -> &[u8] {
+        // SAFETY: ptr must be active under preconditions
+        let bytes = if unsafe { self.is_refcounted() } {
+            // SAFETY: ptr is active and refcounted
+            let (header, offset) = unsafe { self.inner_ptr_parts() };
+            // SAFETY: the bytes follow the header, and offset is within them
+            unsafe { header.add(HEADER_SIZE + offset) }
+        } else {
+            // SAFETY: ptr must be active under preconditions
+            tag::untag(unsafe { self.ptr })
+        };
+        // SAFETY: the bytes are 'static, or live as long as self either through the refcount or the contract of
+        // from_transient
+        unsafe { &*ptr::slice_from_raw_parts(bytes, len as usize) }
+    }
+
+    /// SAFETY: Must be called with ptr field active and refcounted, with the length of the string it was created with, and
+    /// with no other reference to the allocation
+    unsafe fn inner_ptr_bytes_mut(&mut self, len: u32) -> &mut [u8] {
+        // SAFETY: ptr must be active and refcounted under preconditions
+        let (base, offset) = unsafe { self.inner_ptr_parts() };
+        // SAFETY: the bytes follow the header and offset is within them, the allocation came from alloc so may be
+        // written through, and nothing else references it
+        unsafe {
+            &mut *ptr::slice_from_raw_parts_mut(
+                base.cast_mut().add(HEADER_SIZE + offset),
+                len as usize,
+            )
         }
     }
 
+    /// SAFETY: Must be called with ptr field active and refcounted
+    unsafe fn inner_ptr_header(&self) -> &ArcHeader {
+        // SAFETY: ptr must be active and refcounted under preconditions so points into an allocation with a header
+        unsafe { &*self.inner_ptr_parts().0.cast::<ArcHeader>() }
+    }
+
+    /// SAFETY: Must be called with ptr field active and it containing a pointer from inner_ptr_new
+    pub(crate) unsafe fn inner_ptr_clone(&self) -> Self {
+        // SAFETY: ptr must be active under preconditions
+        let ptr = unsafe { self.ptr };
+        // SAFETY: ptr must be active under preconditions
+        if !unsafe { self.is_refcounted() } {
+            return UmbraArcExtra { ptr };
+        }
+
+        // SAFETY: ptr is active and refcounted
+        let header = unsafe { self.inner_ptr_header() };
+
+        // Relaxed is enough here as in Arc::clone, the existing reference keeps the allocation alive
+        let old_count = header.count.fetch_add(1, Ordering::Relaxed);
+        if old_count > isize::MAX as usize {
+            std::process::abort();
+        }
+
+        UmbraArcExtra { ptr }
+    }
+
+    /// SAFETY: Must be called with ptr field active, and holding either a strong or a weak reference
+    unsafe fn strong_count(&self) -> Option<usize> {
+        // SAFETY: ptr must be active under preconditions
+        if !unsafe { self.is_refcounted() } {
+            return None;
+        }
+        // SAFETY: ptr is active and refcounted, and the header outlives every reference
+        let header = unsafe { self.inner_ptr_header() };
+        Some(header.count.load(Ordering::Acquire))
+    }
+
+    /// SAFETY: Must be called with ptr field active, and holding either a strong or a weak reference
+    unsafe fn weak_count(&self) -> Option<usize> {
+        // SAFETY: ptr must be active under preconditions
+        if !unsafe { self.is_refcounted() } {
+            return None;
+        }
+        // SAFETY: ptr is active and refcounted, and the header outlives every reference
+        let header = unsafe { self.inner_ptr_header() };
+        let weak = header.weak.load(Ordering::Acquire);
+        let count = header.count.load(Ordering::Acquire);
+        // the strong references together hold one weak reference, and a locked count means there were none
+        Some(match weak {
+            WEAK_LOCKED => 0,
+            _ if count == 0 => weak as usize,
+            _ => weak as usize - 1,
+        })
+    }
+
+    /// SAFETY: Must be called with ptr field active and holding a strong reference
+    unsafe fn inner_ptr_downgrade(&self) -> Self {
+        // SAFETY: ptr must be active under preconditions
+        let ptr = unsafe { self.ptr };
+        // SAFETY: ptr must be active under preconditions
+        if !unsafe { self.is_refcounted() } {
+            return UmbraArcExtra { ptr };
+        }
+
+        // SAFETY: ptr is active and refcounted
+        let header = unsafe { self.inner_ptr_header() };
+        let mut weak = header.weak.load(Ordering::Relaxed);
+        loop {
+            // the count is only locked for a moment by is_unique
+            if weak == WEAK_LOCKED {
+                sync::spin_loop();
+                weak = header.weak.load(Ordering::Relaxed);
+                continue;
+            }
+            if weak > i32::MAX as u32 {
+                std::process::abort();
+            }
+            // Acquire pairs with the Release store unlocking the count, as in Arc::downgrade
+            match header.weak.compare_exchange_weak(
+                weak,
+                weak + 1,
+                Ordering::Acquire,
+                Ordering::Relaxed,
+            ) {
+                Ok(_) => return UmbraArcExtra { ptr },
+                Err(current) => weak = current,
+            }
+        }
+    }
+
+    /// SAFETY: Must be called with ptr field active and holding a weak reference
+    unsafe fn inner_ptr_upgrade(&self) -> Option<Self> {
+        // SAFETY: ptr must be active under preconditions
+        let ptr = unsafe { self.ptr };
+        // SAFETY: ptr must be active under preconditions
+        if !unsafe { self.is_refcounted() } {
+            return Some(UmbraArcExtra { ptr });
+        }
+
+        // SAFETY: ptr is active and refcounted, the weak reference keeps the header alive
+        let header = unsafe { self.inner_ptr_header() };
+        let mut count = header.count.load(Ordering::Relaxed);
+        loop {
+            // once the count reaches 0 the bytes are no longer kept alive, and it never goes back up
+            if count == 0 {
+                return None;
+            }
+            if count > isize::MAX as usize {
+                std::process::abort();
+            }
+            // Acquire pairs with the Release decrements, as in Weak::upgrade
+            match header.count.compare_exchange_weak(
+                count,
+                count + 1,
+                Ordering::Acquire,
+                Ordering::Relaxed,
+            ) {
+                Ok(_) => return Some(UmbraArcExtra { ptr }),
+                Err(current) => count = current,
+            }
+        }
+    }
+
+    /// SAFETY: Must be called with ptr field active and holding a weak reference
+    unsafe fn inner_ptr_weak_clone(&self) -> Self {
+        // SAFETY: ptr must be active under preconditions
+        let ptr = unsafe { self.ptr };
+        // SAFETY: ptr must be active under preconditions
+        if unsafe { self.is_refcounted() } {
+            // SAFETY: ptr is active and refcounted, the weak reference keeps the header alive
+            let header = unsafe { self.inner_ptr_header() };
+            // Relaxed as in Weak::clone, the existing weak reference keeps the allocation alive. The count can't be
+            // locked, as is_unique only locks it when there are no weak references
+            if header.weak.fetch_add(1, Ordering::Relaxed) > i32::MAX as u32 {
+                std::process::abort();
+            }
+        }
+        UmbraArcExtra { ptr }
+    }
+
+    /// SAFETY: Must be called with ptr field active and holding a weak reference, which is given up
+    unsafe fn inner_ptr_weak_drop(&self) {
+        // SAFETY: ptr must be active under preconditions
+        if unsafe { self.is_refcounted() } {
+            // SAFETY: ptr is active and refcounted, and the weak reference is given up
+            unsafe { self.release_weak() }
+        }
+    }
+
+    /// Gives up a weak reference, freeing the allocation if it was the last
+    ///
+    /// SAFETY: Must be called with ptr field active and refcounted, and holding a weak reference
+    unsafe fn release_weak(&self) {
+        // SAFETY: ptr is active and refcounted under preconditions
+        let header = unsafe { self.inner_ptr_header() };
+        if header.weak.fetch_sub(1, Ordering::Release) != 1 {
+            return;
+        }
+        // synchronise with the Release decrements of all other references before freeing
+        sync::fence(Ordering::Acquire);
+
+        let layout = common::heap_layout(header.len as usize);
+        // SAFETY: this was the last reference and the allocation was made with the layout for the length in its header
+        unsafe { alloc::dealloc(self.inner_ptr_parts().0.cast_mut(), layout) }
+    }
+
     /// SAFETY: Must be called with data field active
-    unsafe fn inner_data_clone(&self) -> Self {
+    pub(crate) unsafe fn inner_data_clone(&self) -> Self {
         UmbraArcExtra {
             // SAFETY: data must be active under preconditions
-            data: unsafe { self.data.clone() },
+            data: unsafe { self.data },
         }
     }
 
-    /// SAFETY: Must be called with ptr field active and it containing a pointer from Arc::into_raw
-    unsafe fn inner_ptr_drop(&self) {
+    /// SAFETY: Must be called with ptr field active and it containing a pointer from inner_ptr_new
+    pub(crate) unsafe fn inner_ptr_drop(&self) {
         // SAFETY: ptr must be active under preconditions
-        let arc_raw = unsafe { self.ptr };
+        if !unsafe { self.is_refcounted() } {
+            return;
+        }
+
+        // SAFETY: ptr is active and refcounted
+        let header = unsafe { self.inner_ptr_header() };
+
+        if header.count.fetch_sub(1, Ordering::Release) != 1 {
+            return;
+        }
+        // synchronise with the Release decrements of all other references before giving up the weak reference the
+        // strong ones held together, which frees the allocation if there are no UmbraWeakStrings
+        sync::fence(Ordering::Acquire);
 
-        // SAFETY: ptr must have a pointer from Arc::into_raw
-        let _ = unsafe { Arc::from_raw(arc_raw) };
+        // SAFETY: ptr is active and refcounted, and this was the last strong reference
+        unsafe { self.release_weak() }
     }
 }
 
 #[cfg(test)]
 mod test {
-    use super::UmbraArcString;
+    use std::ops::Bound;
+
+    use super::{StorageClass, UmbraArcString};
+    use crate::{common::UmbraLayout, rc::UmbraRcString, UmbraError};
 
     #[test]
     fn basic_test() {
@@ -288,4 +1088,335 @@ mod test {
 
         assert_eq!(umbra, overflow)
     }
+
+    #[test]
+    fn try_new_test() {
+        assert_eq!(UmbraArcString::try_new("short").unwrap(), "short");
+        assert_eq!(
+            UmbraArcString::try_new("abcdefghijklmnopqr").unwrap(),
+            "abcdefghijklmnopqr"
+        );
+        assert_eq!(
+            crate::common::checked_len(u32::MAX as usize + 1),
+            Err(UmbraError::LengthOverflow {
+                len: u32::MAX as usize + 1
+            })
+        );
+    }
+
+    #[test]
+    fn from_static_test() {
+        static INLINE: UmbraArcString = UmbraArcString::from_static("column");
+        static LONG: UmbraArcString = UmbraArcString::from_static("a long enum label");
+
+        assert!(INLINE.is_inline());
+        assert_eq!(INLINE, UmbraArcString::new("column"));
+        assert!(LONG.is_static());
+        assert_eq!(LONG, UmbraArcString::new("a long enum label"));
+        assert_eq!(LONG.clone(), "a long enum label");
+        assert_eq!(LONG.as_umbra_str(), "a long enum label");
+        assert!(!UmbraArcString::new("a long enum label").is_static());
+
+        assert!(LONG.substring(1..).is_static());
+        assert_eq!(LONG.substring(1..), " long enum label");
+        assert!(LONG.clone().try_unwrap().is_err());
+        assert!(LONG.downgrade().upgrade().unwrap().is_static());
+
+        let mut persistent = LONG.clone();
+        assert!(persistent.get_mut().is_none());
+        persistent.make_mut().make_ascii_uppercase();
+        assert_eq!(persistent, "A LONG ENUM LABEL");
+        assert_eq!(persistent.storage_class(), StorageClass::Temporary);
+        assert_eq!(LONG, "a long enum label");
+    }
+
+    #[test]
+    fn storage_class_test() {
+        let page = String::from("a string which lives in a page");
+        // SAFETY: page outlives transient and all its clones, apart from the promoted one
+        let transient = unsafe { UmbraArcString::from_transient(&page) };
+        let cloned = transient.clone();
+        assert_eq!(transient.storage_class(), StorageClass::Transient);
+        assert_eq!(cloned, "a string which lives in a page");
+
+        let promoted = transient.promote();
+        drop(cloned);
+        drop(page);
+        assert_eq!(promoted.storage_class(), StorageClass::Temporary);
+        assert_eq!(promoted, "a string which lives in a page");
+
+        const PERSISTENT: UmbraArcString = UmbraArcString::from_static("a long enum label");
+        assert_eq!(PERSISTENT.storage_class(), StorageClass::Persistent);
+        assert!(PERSISTENT.promote().is_static());
+    }
+
+    #[test]
+    fn clone_drop_test() {
+        let umbra = UmbraArcString::new("a string that lives on the heap");
+        let cloned = umbra.clone();
+        drop(umbra);
+
+        assert_eq!(cloned, "a string that lives on the heap");
+        assert_eq!(cloned.clone(), cloned);
+    }
+
+    #[test]
+    fn substring_test() {
+        let umbra =
+            UmbraArcString::new("a string that lives on the heap, shared by its substrings");
+        let sub = umbra.substring(2..31);
+        assert_eq!(sub, "string that lives on the heap");
+        assert_eq!(umbra.substring(..8), "a string");
+        assert!(umbra.substring(..8).is_inline());
+        assert_eq!(umbra.substring(..), umbra);
+
+        let nested = sub.substring(7..=28);
+        assert_eq!(nested, "that lives on the heap");
+        assert_eq!(nested.substring(5..), "lives on the heap");
+        assert_eq!(nested.storage_class(), StorageClass::Temporary);
+    }
+
+    #[test]
+    fn substring_share_test() {
+        let umbra =
+            UmbraArcString::new("a string that lives on the heap, shared by its substrings");
+        let sub = umbra.substring(2..31);
+        assert_eq!(sub.strong_count(), Some(2));
+
+        let nested = sub.substring(7..=28);
+        drop(umbra);
+        drop(sub);
+        assert_eq!(nested.strong_count(), Some(1));
+        assert_eq!(nested, "that lives on the heap");
+        // the allocation holds more than the substring, so can't be handed over
+        assert!(UmbraRcString::try_from(nested).is_err());
+    }
+
+    /// Only a 64 bit pointer limits how far into the allocation a slice can start
+    #[test]
+    #[cfg(target_pointer_width = "64")]
+    fn substring_copy_test() {
+        let long = "é".repeat(10_000);
+        let umbra = UmbraArcString::new(&long);
+        let sub = umbra.substring(18_000..);
+        assert_eq!(sub, &long[18_000..]);
+        assert_eq!(sub.strong_count(), Some(1));
+        // an offset just under the limit still shares
+        let shared = umbra.substring(16_382..16_400);
+        assert_eq!(shared, &long[16_382..16_400]);
+        assert_eq!(umbra.strong_count(), Some(2));
+        drop(shared);
+
+        let long = "a".repeat(20_000);
+        let umbra = UmbraArcString::new(&long);
+        let sub = umbra.substring(17_000..17_100);
+        assert_eq!(sub, &long[17_000..17_100]);
+        assert_eq!(sub.strong_count(), Some(1));
+        assert_eq!(umbra.strong_count(), Some(1));
+        assert_eq!(umbra.substring(2..), &long[2..]);
+        assert_eq!(umbra.strong_count(), Some(1));
+    }
+
+    #[test]
+    #[should_panic]
+    fn substring_included_overflow_test() {
+        UmbraArcString::new("a string that lives on the heap").substring(..=usize::MAX);
+    }
+
+    #[test]
+    #[should_panic]
+    fn substring_excluded_overflow_test() {
+        UmbraArcString::new("a string that lives on the heap")
+            .substring((Bound::Excluded(usize::MAX), Bound::Unbounded));
+    }
+
+    #[test]
+    #[should_panic]
+    fn substring_char_boundary_test() {
+        UmbraArcString::new("résumé résumé résumé").substring(2..);
+    }
+
+    #[test]
+    fn make_mut_test() {
+        let mut inline = UmbraArcString::new("short");
+        inline.get_mut().unwrap().make_ascii_uppercase();
+        assert_eq!(inline, "SHORT");
+
+        let mut umbra = UmbraArcString::new("a string that lives on the heap");
+        let shared = umbra.clone();
+        assert!(umbra.get_mut().is_none());
+        umbra.make_mut().make_ascii_uppercase();
+        assert_eq!(umbra, "A STRING THAT LIVES ON THE HEAP");
+        assert_eq!(umbra.raw_prefix(), *b"A ST");
+        assert_eq!(shared, "a string that lives on the heap");
+
+        let mut sub = shared.substring(2..);
+        drop(shared);
+        sub.get_mut().unwrap().make_ascii_uppercase();
+        assert_eq!(sub, "STRING THAT LIVES ON THE HEAP");
+    }
+
+    #[test]
+    fn try_unwrap_test() {
+        assert_eq!(UmbraArcString::new("short").try_unwrap().unwrap(), "short");
+
+        let umbra = UmbraArcString::new("a string that lives on the heap");
+        let shared = umbra.clone();
+        let umbra = umbra.try_unwrap().unwrap_err();
+        drop(shared);
+        assert_eq!(
+            umbra.try_unwrap().unwrap(),
+            "a string that lives on the heap"
+        );
+    }
+
+    #[test]
+    fn weak_test() {
+        let umbra = UmbraArcString::new("a string that lives on the heap");
+        let weak = umbra.downgrade();
+        assert_eq!(umbra.strong_count(), Some(1));
+        assert_eq!(umbra.weak_count(), Some(1));
+
+        let upgraded = weak.upgrade().unwrap();
+        assert_eq!(upgraded, umbra);
+        assert_eq!(weak.strong_count(), Some(2));
+        let cloned = weak.clone();
+        assert_eq!(weak.weak_count(), Some(2));
+
+        drop(umbra);
+        drop(upgraded);
+        assert!(weak.upgrade().is_none());
+        assert_eq!(cloned.strong_count(), Some(0));
+        assert_eq!(cloned.weak_count(), Some(2));
+
+        let inline = UmbraArcString::new("short").downgrade();
+        assert_eq!(inline.upgrade().unwrap(), "short");
+        assert_eq!(inline.strong_count(), None);
+    }
+
+    #[test]
+    fn weak_unique_test() {
+        let mut umbra = UmbraArcString::new("a string that lives on the heap");
+        let sub = umbra.substring(2..);
+        let weak = sub.downgrade();
+        drop(sub);
+        assert!(umbra.get_mut().is_none());
+        // the weak reference is to the whole allocation, which umbra keeps alive
+        assert_eq!(weak.upgrade().unwrap(), "string that lives on the heap");
+
+        drop(weak);
+        assert!(umbra.get_mut().is_some());
+        assert_eq!(
+            umbra.try_unwrap().unwrap(),
+            "a string that lives on the heap"
+        );
+    }
+
+    #[test]
+    fn cmp_test() {
+        let short = UmbraArcString::new("abcdefghijklmnop");
+        let long = UmbraArcString::new("abcdefghijklmnopq");
+        let other = UmbraArcString::new("abcdefghijklmnoz");
+
+        assert_ne!(short, other);
+        assert!(short < long);
+        assert!(long < other);
+        assert!(UmbraArcString::new("ab") < UmbraArcString::new("ab\0"));
+    }
+
+    #[test]
+    fn threads_test() {
+        let umbra = UmbraArcString::new("a string shared between threads");
+        let weak = umbra.downgrade();
+        let handles: Vec<_> = (0..8)
+            .map(|_| {
+                let (umbra, weak) = (umbra.clone(), weak.clone());
+                std::thread::spawn(move || {
+                    for i in 0..1000 {
+                        let cloned = umbra.clone();
+                        let sub = cloned.substring(2..);
+                        assert_eq!(weak.upgrade().unwrap(), umbra);
+                        let mut copied = sub.clone();
+                        // another thread holds a clone of the allocation, so this always copies
+                        copied.make_mut().make_ascii_uppercase();
+                        assert_eq!(copied, "STRING SHARED BETWEEN THREADS");
+                        if i % 2 == 0 {
+                            drop(cloned);
+                        }
+                        drop(sub.downgrade());
+                    }
+                    umbra
+                })
+            })
+            .collect();
+
+        let returned: Vec<_> = handles
+            .into_iter()
+            .map(|handle| handle.join().unwrap())
+            .collect();
+        assert_eq!(umbra.strong_count(), Some(9));
+        drop(returned);
+        assert_eq!(umbra.strong_count(), Some(1));
+        assert_eq!(umbra.weak_count(), Some(1));
+        drop(umbra);
+        assert!(weak.upgrade().is_none());
+    }
+}
+
+/// Model tests of the refcounts, which loom runs under every interleaving of the threads. They need `--cfg loom` and
+/// only make sense in release mode: `RUSTFLAGS="--cfg loom" cargo test --release loom_test`
+#[cfg(all(test, loom))]
+mod loom_test {
+    use loom::thread;
+
+    use super::UmbraArcString;
+
+    const LONG: &str = "a string that lives on the heap";
+
+    #[test]
+    fn clone_drop_test() {
+        loom::model(|| {
+            let umbra = UmbraArcString::new(LONG);
+            let cloned = umbra.clone();
+            let handle = thread::spawn(move || {
+                assert_eq!(cloned.substring(2..), &LONG[2..]);
+            });
+            assert_eq!(umbra, LONG);
+            drop(umbra);
+            handle.join().unwrap();
+        });
+    }
+
+    #[test]
+    fn upgrade_test() {
+        loom::model(|| {
+            let umbra = UmbraArcString::new(LONG);
+            let weak = umbra.downgrade();
+            let handle = thread::spawn(move || drop(umbra));
+            if let Some(upgraded) = weak.upgrade() {
+                assert_eq!(upgraded, LONG);
+            }
+            handle.join().unwrap();
+            assert!(weak.upgrade().is_none());
+        });
+    }
+
+    #[test]
+    fn unique_test() {
+        loom::model(|| {
+            let mut umbra = UmbraArcString::new(LONG);
+            let cloned = umbra.clone();
+            // the clone becomes a weak reference, so there is always another reference to the allocation
+            let handle = thread::spawn(move || {
+                let weak = cloned.downgrade();
+                drop(cloned);
+                weak
+            });
+            assert!(umbra.get_mut().is_none());
+            let weak = handle.join().unwrap();
+            assert!(umbra.get_mut().is_none());
+            drop(weak);
+            assert!(umbra.get_mut().is_some());
+        });
+    }
 }
@@ -0,0 +1,374 @@
+//! Columns compatible with Apache Arrow's StringView layout. A view is 16 bytes like an UmbraArcString: the length,
+//! then either the whole string when it is at most 12 bytes, or a 4 byte prefix followed by the index of a data
+//! buffer and an offset into it, in place of the pointer.
+
+use core::str;
+use std::{iter::FusedIterator, ptr};
+
+pub mod ffi;
+
+use crate::{
+    arc::{UmbraArcExtra, UmbraArcString, MAX_INLINE},
+    common::{self, UmbraLayout},
+    UmbraError,
+};
+
+/// Arrow stores lengths, buffer indexes and offsets as i32
+const MAX_ARROW_LEN: usize = i32::MAX as usize;
+
+/// A column of strings in the Arrow StringView layout, made of u128 views and the data buffers they point into
+#[derive(Debug, Clone, Default)]
+pub struct StringViewArray {
+    views: Vec<u128>,
+    buffers: Vec<Vec<u8>>,
+}
+
+fn view_len(view: u128) -> usize {
+    view as u32 as usize
+}
+
+fn view_prefix(view: u128) -> [u8; 4] {
+    ((view >> 32) as u32).to_le_bytes()
+}
+
+fn view_buffer(view: u128) -> usize {
+    (view >> 64) as u32 as usize
+}
+
+fn view_offset(view: u128) -> usize {
+    (view >> 96) as u32 as usize
+}
+
+fn view_bytes(view: &u128) -> &[u8; 16] {
+    // SAFETY: u128 has no padding so all its bytes are initialised, and u8 has no alignment requirement
+    unsafe { &*ptr::from_ref(view).cast::<[u8; 16]>() }
+}
+
+fn inline_view(len: u32, prefix: [u8; 4], data: [u8; 8]) -> u128 {
+    u128::from(len)
+        | u128::from(u32::from_le_bytes(prefix)) << 32
+        | u128::from(u64::from_le_bytes(data)) << 64
+}
+
+impl StringViewArray {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_capacity(capacity: usize) -> Self {
+        Self {
+            views: Vec::with_capacity(capacity),
+            buffers: Vec::new(),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.views.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.views.is_empty()
+    }
+
+    /// Appends a string, inline strings are copied over as they are
+    ///
+    /// Panics if the string is longer than i32::MAX bytes
+    pub fn push(&mut self, val: &UmbraArcString) {
+        match val.inline_data() {
+            Some(data) => self
+                .views
+                .push(inline_view(val.raw_len(), val.raw_prefix(), *data)),
+            None => self.push_str(val),
+        }
+    }
+
+    /// Panics if the string is longer than i32::MAX bytes
+    pub fn push_str(&mut self, val: &str) {
+        let len = val.len();
+        assert!(len <= MAX_ARROW_LEN, "string longer than i32::MAX");
+
+        if len <= MAX_INLINE {
+            let (prefix, data) = common::split_inline(val.as_bytes());
+            self.views.push(inline_view(len as u32, prefix, data));
+            return;
+        }
+
+        let needs_buffer = match self.buffers.last() {
+            Some(buffer) => buffer.len() + len > buffer.capacity(),
+            None => true,
+        };
+        if needs_buffer {
+            assert!(self.buffers.len() < MAX_ARROW_LEN, "too many data buffers");
+            let capacity = common::next_buffer_size(self.buffers.len(), len);
+            self.buffers.push(Vec::with_capacity(capacity));
+        }
+
+        let buffer_index = self.buffers.len() - 1;
+        let buffer = &mut self.buffers[buffer_index];
+        let offset = buffer.len();
+        buffer.extend_from_slice(val.as_bytes());
+
+        let prefix = common::heap_prefix(val.as_bytes());
+        self.views.push(
+            len as u128
+                | u128::from(u32::from_le_bytes(prefix)) << 32
+                | (buffer_index as u128) << 64
+                | (offset as u128) << 96,
+        );
+    }
+
+    pub fn value(&self, index: usize) -> &str {
+        // SAFETY: views are only built from strs or checked in try_from_parts
+        unsafe {
+            view_str(view_bytes(&self.views[index]), |buffer| {
+                &self.buffers[buffer]
+            })
+        }
+    }
+
+    /// Gets a string as an UmbraArcString, this only allocates if the string isn't inline
+    pub fn value_umbra(&self, index: usize) -> UmbraArcString {
+        // SAFETY: views are only built from strs or checked in try_from_parts
+        unsafe {
+            view_umbra(view_bytes(&self.views[index]), |buffer| {
+                &self.buffers[buffer]
+            })
+        }
+    }
+
+    pub fn iter(&self) -> Iter<'_> {
+        Iter {
+            array: self,
+            index: 0,
+        }
+    }
+
+    pub fn views(&self) -> &[u128] {
+        &self.views
+    }
+
+    pub fn buffers(&self) -> &[Vec<u8>] {
+        &self.buffers
+    }
+
+    /// Builds an array from views and buffers made elsewhere, such as by an Arrow implementation, checking every view
+    pub fn try_from_parts(views: Vec<u128>, buffers: Vec<Vec<u8>>) -> Result<Self, UmbraError> {
+        for (index, view) in views.iter().enumerate() {
+            if !view_is_valid(view_bytes(view), |buffer| {
+                buffers.get(buffer).map(Vec::as_slice)
+            }) {
+                return Err(UmbraError::InvalidView { index });
+            }
+        }
+
+        Ok(Self { views, buffers })
+    }
+
+    /// Splits the array back into its views and buffers
+    pub fn into_parts(self) -> (Vec<u128>, Vec<Vec<u8>>) {
+        (self.views, self.buffers)
+    }
+}
+
+/// SAFETY: the view must describe a valid string, as checked by view_is_valid
+unsafe fn view_str<'a>(view: &'a [u8; 16], buffer: impl FnOnce(usize) -> &'a [u8]) -> &'a str {
+    let view_bits = u128::from_le_bytes(*view);
+    let len = view_len(view_bits);
+    let bytes = if len <= MAX_INLINE {
+        &view[4..4 + len]
+    } else {
+        let offset = view_offset(view_bits);
+        &buffer(view_buffer(view_bits))[offset..offset + len]
+    };
+    // SAFETY: the view is valid so the bytes are utf-8
+    unsafe { str::from_utf8_unchecked(bytes) }
+}
+
+/// SAFETY: the view must describe a valid string, as checked by view_is_valid
+unsafe fn view_umbra<'a>(
+    view: &'a [u8; 16],
+    buffer: impl FnOnce(usize) -> &'a [u8],
+) -> UmbraArcString {
+    let view_bits = u128::from_le_bytes(*view);
+    let len = view_len(view_bits);
+    if len <= MAX_INLINE {
+        let data = ((view_bits >> 64) as u64).to_le_bytes();
+        // SAFETY: the view is laid out like an inline UmbraArcString, and its bytes are utf-8 as it is valid
+        unsafe {
+            UmbraArcString::from_raw_parts(
+                len as u32,
+                view_prefix(view_bits),
+                UmbraArcExtra::inline(data),
+            )
+        }
+    } else {
+        // SAFETY: the view is valid
+        UmbraArcString::new(unsafe { view_str(view, buffer) })
+    }
+}
+
+/// Checks a view describes a valid string, given a way to look up its data buffers
+fn view_is_valid<'a>(view: &[u8; 16], buffer: impl FnOnce(usize) -> Option<&'a [u8]>) -> bool {
+    let len = view_len(u128::from_le_bytes(*view));
+    if len > MAX_ARROW_LEN {
+        return false;
+    }
+
+    if len <= MAX_INLINE {
+        let bytes = &view[4..];
+        // inline views are zero padded, and equality relies on that
+        return bytes[len..].iter().all(|&byte| byte == 0) && str::from_utf8(&bytes[..len]).is_ok();
+    }
+
+    let view = u128::from_le_bytes(*view);
+    let offset = view_offset(view);
+    let Some(bytes) =
+        buffer(view_buffer(view)).and_then(|buffer| buffer.get(offset..offset.checked_add(len)?))
+    else {
+        return false;
+    };
+    bytes[..4] == view_prefix(view) && str::from_utf8(bytes).is_ok()
+}
+
+impl FromIterator<UmbraArcString> for StringViewArray {
+    fn from_iter<T: IntoIterator<Item = UmbraArcString>>(iter: T) -> Self {
+        let mut array = StringViewArray::new();
+        array.extend(iter);
+        array
+    }
+}
+
+impl<'a> FromIterator<&'a UmbraArcString> for StringViewArray {
+    fn from_iter<T: IntoIterator<Item = &'a UmbraArcString>>(iter: T) -> Self {
+        let mut array = StringViewArray::new();
+        array.extend(iter);
+        array
+    }
+}
+
+impl Extend<UmbraArcString> for StringViewArray {
+    fn extend<T: IntoIterator<Item = UmbraArcString>>(&mut self, iter: T) {
+        for val in iter {
+            self.push(&val);
+        }
+    }
+}
+
+impl<'a> Extend<&'a UmbraArcString> for StringViewArray {
+    fn extend<T: IntoIterator<Item = &'a UmbraArcString>>(&mut self, iter: T) {
+        for val in iter {
+            self.push(val);
+        }
+    }
+}
+
+impl<'a> IntoIterator for &'a StringViewArray {
+    type Item = &'a str;
+    type IntoIter = Iter<'a>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+/// Iterator over the strings of a StringViewArray
+pub struct Iter<'a> {
+    array: &'a StringViewArray,
+    index: usize,
+}
+
+impl<'a> Iterator for Iter<'a> {
+    type Item = &'a str;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.index == self.array.len() {
+            return None;
+        }
+        self.index += 1;
+        Some(self.array.value(self.index - 1))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let remaining = self.array.len() - self.index;
+        (remaining, Some(remaining))
+    }
+}
+
+impl ExactSizeIterator for Iter<'_> {}
+
+impl FusedIterator for Iter<'_> {}
+
+#[cfg(test)]
+mod test {
+    use std::ptr;
+
+    use super::StringViewArray;
+    use crate::{arc::UmbraArcString, UmbraError};
+
+    #[test]
+    fn round_trip_test() {
+        let strings = [
+            "short",
+            "",
+            "a string which is too long to inline",
+            "twelve bytes",
+        ];
+        let array: StringViewArray = strings.iter().map(UmbraArcString::new).collect();
+
+        assert_eq!(array.len(), 4);
+        assert_eq!(array.iter().collect::<Vec<_>>(), strings);
+        for (i, val) in strings.iter().enumerate() {
+            assert_eq!(array.value_umbra(i), *val);
+        }
+    }
+
+    #[test]
+    fn buffer_growth_test() {
+        let mut array = StringViewArray::new();
+        array.push_str("thirteen byte");
+        assert!(array.buffers()[0].capacity() < 16 * 1024);
+
+        let long = "a string which is too long to inline";
+        for _ in 0..100_000 {
+            array.push_str(long);
+        }
+        let capacities: Vec<usize> = array.buffers().iter().map(Vec::capacity).collect();
+        assert!(capacities.windows(2).all(|pair| pair[1] >= pair[0]));
+        assert_eq!(capacities.iter().max(), Some(&(2 * 1024 * 1024)));
+        assert_eq!(array.value(100_000), long);
+
+        let huge = "x".repeat(3 * 1024 * 1024);
+        array.push_str(&huge);
+        assert_eq!(array.value(100_001), huge);
+    }
+
+    #[test]
+    fn inline_layout_test() {
+        let umbra = UmbraArcString::new("short");
+        let array = StringViewArray::from_iter([&umbra]);
+
+        // SAFETY: UmbraArcString is 16 bytes with no padding, and an inline string has all of them initialised
+        let bits = unsafe { ptr::from_ref(&umbra).cast::<u128>().read_unaligned() };
+        assert_eq!(array.views()[0], bits);
+    }
+
+    #[test]
+    fn try_from_parts_test() {
+        let array: StringViewArray = ["a string which is too long to inline", "short"]
+            .iter()
+            .map(UmbraArcString::new)
+            .collect();
+        let (views, buffers) = array.into_parts();
+
+        let array = StringViewArray::try_from_parts(views.clone(), buffers.clone()).unwrap();
+        assert_eq!(array.value(0), "a string which is too long to inline");
+
+        let mut bad_views = views.clone();
+        bad_views[0] += 1;
+        assert_eq!(
+            StringViewArray::try_from_parts(bad_views, buffers).unwrap_err(),
+            UmbraError::InvalidView { index: 0 }
+        );
+        assert!(StringViewArray::try_from_parts(views, Vec::new()).is_err());
+    }
+}
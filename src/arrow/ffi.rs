@@ -0,0 +1,536 @@
+//! The Arrow C Data Interface, for handing string columns to and from other Arrow implementations without copying.
+//! The structs are declared here exactly as in the specification, so neither side needs to link against an Arrow
+//! library. Columns are exchanged with the `vu` (utf8_view) format, whose buffers are an optional validity bitmap,
+//! the views, each data buffer, and finally an i64 array of the data buffer sizes.
+
+use std::{
+    ffi::{c_char, c_void, CStr},
+    ptr, slice, str,
+};
+
+use super::{view_is_valid, view_str, view_umbra, StringViewArray};
+use crate::{arc::UmbraArcString, UmbraError};
+
+/// Format string of the utf8_view type
+const FORMAT: &CStr = c"vu";
+
+/// Schema flag marking a field as nullable
+pub const ARROW_FLAG_NULLABLE: i64 = 2;
+
+/// The ArrowSchema struct of the C Data Interface, describing the type of an ArrowArray
+///
+/// Dropping a schema which hasn't been released calls its release callback.
+#[repr(C)]
+#[derive(Debug)]
+pub struct ArrowSchema {
+    pub format: *const c_char,
+    pub name: *const c_char,
+    pub metadata: *const c_char,
+    pub flags: i64,
+    pub n_children: i64,
+    pub children: *mut *mut ArrowSchema,
+    pub dictionary: *mut ArrowSchema,
+    pub release: Option<unsafe extern "C" fn(*mut ArrowSchema)>,
+    pub private_data: *mut c_void,
+}
+
+/// The ArrowArray struct of the C Data Interface, holding the buffers of a column
+///
+/// Dropping an array which hasn't been released calls its release callback.
+#[repr(C)]
+#[derive(Debug)]
+pub struct ArrowArray {
+    pub length: i64,
+    pub null_count: i64,
+    pub offset: i64,
+    pub n_buffers: i64,
+    pub n_children: i64,
+    pub buffers: *mut *const c_void,
+    pub children: *mut *mut ArrowArray,
+    pub dictionary: *mut ArrowArray,
+    pub release: Option<unsafe extern "C" fn(*mut ArrowArray)>,
+    pub private_data: *mut c_void,
+}
+
+impl ArrowSchema {
+    /// A released schema, for a producer to fill in
+    pub fn empty() -> Self {
+        Self {
+            format: ptr::null(),
+            name: ptr::null(),
+            metadata: ptr::null(),
+            flags: 0,
+            n_children: 0,
+            children: ptr::null_mut(),
+            dictionary: ptr::null_mut(),
+            release: None,
+            private_data: ptr::null_mut(),
+        }
+    }
+
+    pub fn is_released(&self) -> bool {
+        self.release.is_none()
+    }
+}
+
+impl Drop for ArrowSchema {
+    fn drop(&mut self) {
+        if let Some(release) = self.release {
+            // SAFETY: the producer set release to be called exactly once, and it marks the schema released
+            unsafe { release(self) }
+        }
+    }
+}
+
+impl ArrowArray {
+    /// A released array, for a producer to fill in
+    pub fn empty() -> Self {
+        Self {
+            length: 0,
+            null_count: 0,
+            offset: 0,
+            n_buffers: 0,
+            n_children: 0,
+            buffers: ptr::null_mut(),
+            children: ptr::null_mut(),
+            dictionary: ptr::null_mut(),
+            release: None,
+            private_data: ptr::null_mut(),
+        }
+    }
+
+    pub fn is_released(&self) -> bool {
+        self.release.is_none()
+    }
+}
+
+impl Drop for ArrowArray {
+    fn drop(&mut self) {
+        if let Some(release) = self.release {
+            // SAFETY: the producer set release to be called exactly once, and it marks the array released
+            unsafe { release(self) }
+        }
+    }
+}
+
+/// SAFETY: schema must be one made by export_schema
+unsafe extern "C" fn release_schema(schema: *mut ArrowSchema) {
+    // SAFETY: the consumer passes the schema being released, which owns nothing as the format is static
+    unsafe { (*schema).release = None };
+}
+
+/// What an exported array keeps alive until it is released
+struct ExportedArray {
+    array: StringViewArray,
+    buffer_sizes: Vec<i64>,
+    buffers: Vec<*const c_void>,
+}
+
+/// SAFETY: array must be one made by StringViewArray::into_ffi
+unsafe extern "C" fn release_array(array: *mut ArrowArray) {
+    // SAFETY: the consumer passes the array being released, whose private data is the boxed ExportedArray
+    unsafe {
+        drop(Box::from_raw((*array).private_data.cast::<ExportedArray>()));
+        (*array).private_data = ptr::null_mut();
+        (*array).release = None;
+    }
+}
+
+impl StringViewArray {
+    /// Exports the array through the C Data Interface, the buffers are handed over as they are and freed on release
+    pub fn into_ffi(self) -> (ArrowArray, ArrowSchema) {
+        let mut exported = Box::new(ExportedArray {
+            buffer_sizes: self
+                .buffers
+                .iter()
+                .map(|buffer| buffer.len() as i64)
+                .collect(),
+            array: self,
+            buffers: Vec::new(),
+        });
+
+        // moving the vectors into the box didn't move their contents, so these pointers stay valid until release
+        let mut buffers = Vec::with_capacity(exported.array.buffers.len() + 3);
+        buffers.push(ptr::null());
+        buffers.push(exported.array.views.as_ptr().cast());
+        buffers.extend(
+            exported
+                .array
+                .buffers
+                .iter()
+                .map(|buffer| buffer.as_ptr().cast()),
+        );
+        buffers.push(exported.buffer_sizes.as_ptr().cast());
+        exported.buffers = buffers;
+
+        let array = ArrowArray {
+            length: exported.array.len() as i64,
+            null_count: 0,
+            offset: 0,
+            n_buffers: exported.buffers.len() as i64,
+            n_children: 0,
+            buffers: exported.buffers.as_mut_ptr(),
+            children: ptr::null_mut(),
+            dictionary: ptr::null_mut(),
+            release: Some(release_array),
+            private_data: Box::into_raw(exported).cast(),
+        };
+        (array, export_schema())
+    }
+}
+
+fn export_schema() -> ArrowSchema {
+    ArrowSchema {
+        format: FORMAT.as_ptr(),
+        release: Some(release_schema),
+        ..ArrowSchema::empty()
+    }
+}
+
+/// A utf8_view column imported through the C Data Interface, reading the producer's buffers in place
+///
+/// The producer's release callback is called when this is dropped.
+#[derive(Debug)]
+pub struct ImportedStringViewArray {
+    array: ArrowArray,
+    views: *const [u8; 16],
+    len: usize,
+    validity: *const u8,
+    offset: usize,
+    buffers: Vec<*const [u8]>,
+}
+
+fn invalid(reason: &'static str) -> UmbraError {
+    UmbraError::InvalidArray { reason }
+}
+
+impl ImportedStringViewArray {
+    /// Takes ownership of an array, checking it is a utf8_view array and that every view which isn't null is valid
+    ///
+    /// # Safety
+    ///
+    /// array and schema must follow the C Data Interface, so every buffer they describe is readable and stays alive
+    /// and unchanged until the array is released
+    pub unsafe fn try_from_ffi(
+        array: ArrowArray,
+        schema: &ArrowSchema,
+    ) -> Result<Self, UmbraError> {
+        if array.is_released() || schema.is_released() {
+            return Err(invalid("released"));
+        }
+        // SAFETY: a schema which isn't released has a nul terminated format string
+        if schema.format.is_null() || unsafe { CStr::from_ptr(schema.format) } != FORMAT {
+            return Err(invalid("format is not utf8_view"));
+        }
+        if array.n_children != 0 || !array.dictionary.is_null() {
+            return Err(invalid("utf8_view arrays have no children"));
+        }
+        let (Ok(len), Ok(offset)) = (usize::try_from(array.length), usize::try_from(array.offset))
+        else {
+            return Err(invalid("negative length or offset"));
+        };
+        let n_buffers = match usize::try_from(array.n_buffers) {
+            Ok(n_buffers) if n_buffers >= 3 && !array.buffers.is_null() => n_buffers,
+            _ => return Err(invalid("missing buffers")),
+        };
+
+        // SAFETY: the array has n_buffers buffers
+        let buffer_ptrs = unsafe { slice::from_raw_parts(array.buffers, n_buffers) };
+        let views = buffer_ptrs[1].cast::<[u8; 16]>();
+        if views.is_null() && len > 0 {
+            return Err(invalid("missing views buffer"));
+        }
+
+        let data_ptrs = &buffer_ptrs[2..n_buffers - 1];
+        let sizes = buffer_ptrs[n_buffers - 1].cast::<i64>();
+        if sizes.is_null() && !data_ptrs.is_empty() {
+            return Err(invalid("missing buffer sizes"));
+        }
+        let mut buffers = Vec::with_capacity(data_ptrs.len());
+        for (i, &data) in data_ptrs.iter().enumerate() {
+            // SAFETY: there is a size for every data buffer, and i64 buffers are aligned
+            let Ok(size) = usize::try_from(unsafe { sizes.add(i).read() }) else {
+                return Err(invalid("negative buffer size"));
+            };
+            if data.is_null() && size > 0 {
+                return Err(invalid("missing data buffer"));
+            }
+            let data = if size == 0 {
+                ptr::NonNull::dangling().as_ptr()
+            } else {
+                data.cast()
+            };
+            buffers.push(ptr::slice_from_raw_parts(data, size));
+        }
+
+        let imported = Self {
+            validity: buffer_ptrs[0].cast(),
+            // SAFETY: the views buffer holds offset + len views
+            views: if len > 0 {
+                unsafe { views.add(offset) }
+            } else {
+                ptr::NonNull::dangling().as_ptr()
+            },
+            len,
+            offset,
+            buffers,
+            array,
+        };
+        for index in 0..len {
+            if imported.is_null(index) {
+                continue;
+            }
+            // SAFETY: index is in bounds, and the data buffers are readable for as long as imported lives
+            let valid = view_is_valid(unsafe { &*imported.views.add(index) }, |buffer| {
+                imported
+                    .buffers
+                    .get(buffer)
+                    .map(|&buffer| unsafe { &*buffer })
+            });
+            if !valid {
+                return Err(UmbraError::InvalidView { index });
+            }
+        }
+        Ok(imported)
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Panics if index is out of bounds
+    pub fn is_null(&self, index: usize) -> bool {
+        assert!(index < self.len, "index out of bounds");
+        if self.validity.is_null() {
+            return false;
+        }
+        let bit = self.offset + index;
+        // SAFETY: the validity bitmap has a bit for each of the offset + len slots
+        unsafe { *self.validity.add(bit / 8) >> (bit % 8) & 1 == 0 }
+    }
+
+    fn view(&self, index: usize) -> &[u8; 16] {
+        assert!(index < self.len, "index out of bounds");
+        // SAFETY: index is in bounds
+        unsafe { &*self.views.add(index) }
+    }
+
+    /// Gets a string borrowed from the producer's buffers, a null is read as an empty string
+    pub fn value(&self, index: usize) -> &str {
+        if self.is_null(index) {
+            return "";
+        }
+        // SAFETY: the view isn't null so was checked in try_from_ffi, and the buffers live as long as self
+        unsafe { view_str(self.view(index), |buffer| &*self.buffers[buffer]) }
+    }
+
+    /// Gets a string as an UmbraArcString, this only allocates if the string isn't inline
+    pub fn value_umbra(&self, index: usize) -> UmbraArcString {
+        if self.is_null(index) {
+            return UmbraArcString::new("");
+        }
+        // SAFETY: as in value
+        unsafe { view_umbra(self.view(index), |buffer| &*self.buffers[buffer]) }
+    }
+
+    /// Iterates over the strings, with None for nulls
+    pub fn iter(&self) -> impl ExactSizeIterator<Item = Option<&str>> + '_ {
+        (0..self.len).map(|index| (!self.is_null(index)).then(|| self.value(index)))
+    }
+
+    /// Gives back the array, without releasing it
+    pub fn into_ffi(self) -> ArrowArray {
+        self.array
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        ffi::{c_void, CStr},
+        ptr, slice,
+        sync::atomic::{AtomicBool, Ordering},
+    };
+
+    use super::{ArrowArray, ArrowSchema, ImportedStringViewArray};
+    use crate::{arc::UmbraArcString, arrow::StringViewArray, UmbraError};
+
+    const LONG: &str = "a string which is too long to inline";
+
+    /// Stands in for a C consumer, reading the views by hand then releasing both structs
+    unsafe fn c_consume(array: *mut ArrowArray, schema: *mut ArrowSchema) -> Vec<String> {
+        let (array_ref, schema_ref) = unsafe { (&*array, &*schema) };
+        assert_eq!(unsafe { CStr::from_ptr(schema_ref.format) }, c"vu");
+        assert_eq!(array_ref.null_count, 0);
+
+        let buffers =
+            unsafe { slice::from_raw_parts(array_ref.buffers, array_ref.n_buffers as usize) };
+        let views = buffers[1].cast::<[u8; 16]>();
+        let mut strings = Vec::new();
+        for i in 0..array_ref.length as usize {
+            let view = unsafe { *views.add(i) };
+            let len = u32::from_le_bytes(view[0..4].try_into().unwrap()) as usize;
+            let bytes = if len <= 12 {
+                view[4..4 + len].to_vec()
+            } else {
+                let buffer = u32::from_le_bytes(view[8..12].try_into().unwrap()) as usize;
+                let offset = u32::from_le_bytes(view[12..16].try_into().unwrap()) as usize;
+                let data = buffers[2 + buffer].cast::<u8>();
+                unsafe { slice::from_raw_parts(data.add(offset), len) }.to_vec()
+            };
+            strings.push(String::from_utf8(bytes).unwrap());
+        }
+
+        unsafe {
+            (array_ref.release.unwrap())(array);
+            (schema_ref.release.unwrap())(schema);
+        }
+        strings
+    }
+
+    static PRODUCER_RELEASED: AtomicBool = AtomicBool::new(false);
+
+    /// The buffers of the array built by c_produce
+    struct Produced {
+        validity: [u8; 1],
+        views: [[u8; 16]; 3],
+        data: Vec<u8>,
+        sizes: [i64; 1],
+        buffers: [*const c_void; 4],
+    }
+
+    unsafe extern "C" fn c_release_array(array: *mut ArrowArray) {
+        unsafe {
+            drop(Box::from_raw((*array).private_data.cast::<Produced>()));
+            (*array).release = None;
+        }
+        PRODUCER_RELEASED.store(true, Ordering::Relaxed);
+    }
+
+    unsafe extern "C" fn c_release_schema(schema: *mut ArrowSchema) {
+        unsafe { (*schema).release = None };
+    }
+
+    /// Stands in for a C producer, building [LONG, null, "short"] by hand
+    unsafe extern "C" fn c_produce(array: *mut ArrowArray, schema: *mut ArrowSchema) {
+        let mut produced = Box::new(Produced {
+            validity: [0b101],
+            views: [[0; 16]; 3],
+            data: format!("xx{LONG}").into_bytes(),
+            sizes: [LONG.len() as i64 + 2],
+            buffers: [ptr::null(); 4],
+        });
+        produced.views[0][0..4].copy_from_slice(&(LONG.len() as u32).to_le_bytes());
+        produced.views[0][4..8].copy_from_slice(&LONG.as_bytes()[..4]);
+        produced.views[0][12..16].copy_from_slice(&2u32.to_le_bytes());
+        produced.views[2][0..4].copy_from_slice(&5u32.to_le_bytes());
+        produced.views[2][4..9].copy_from_slice(b"short");
+        produced.buffers = [
+            produced.validity.as_ptr().cast(),
+            produced.views.as_ptr().cast(),
+            produced.data.as_ptr().cast(),
+            produced.sizes.as_ptr().cast(),
+        ];
+
+        unsafe {
+            array.write(ArrowArray {
+                length: 3,
+                null_count: 1,
+                n_buffers: 4,
+                buffers: produced.buffers.as_mut_ptr(),
+                release: Some(c_release_array),
+                private_data: Box::into_raw(produced).cast(),
+                ..ArrowArray::empty()
+            });
+            schema.write(ArrowSchema {
+                format: c"vu".as_ptr(),
+                flags: super::ARROW_FLAG_NULLABLE,
+                release: Some(c_release_schema),
+                ..ArrowSchema::empty()
+            });
+        }
+    }
+
+    #[test]
+    fn export_test() {
+        let array: StringViewArray = ["short", LONG, ""]
+            .iter()
+            .map(UmbraArcString::new)
+            .collect();
+        let (mut ffi_array, mut ffi_schema) = array.into_ffi();
+
+        let strings = unsafe { c_consume(&mut ffi_array, &mut ffi_schema) };
+        assert_eq!(strings, ["short", LONG, ""]);
+        assert!(ffi_array.is_released() && ffi_schema.is_released());
+    }
+
+    #[test]
+    fn import_test() {
+        let mut array = ArrowArray::empty();
+        let mut schema = ArrowSchema::empty();
+        unsafe { c_produce(&mut array, &mut schema) };
+        let data = unsafe { *array.buffers.add(2) }.cast::<u8>();
+
+        let imported = unsafe { ImportedStringViewArray::try_from_ffi(array, &schema) }.unwrap();
+        assert_eq!(
+            imported.iter().collect::<Vec<_>>(),
+            [Some(LONG), None, Some("short")]
+        );
+        assert_eq!(imported.value(0).as_ptr(), data.wrapping_add(2));
+        assert_eq!(imported.value_umbra(2), "short");
+
+        drop(imported);
+        assert!(PRODUCER_RELEASED.load(Ordering::Relaxed));
+    }
+
+    #[test]
+    fn round_trip_test() {
+        let strings = [
+            "skipped",
+            LONG,
+            "twelve bytes",
+            "another string too long to inline",
+        ];
+        let array: StringViewArray = strings.iter().map(UmbraArcString::new).collect();
+        let views = array.views().as_ptr();
+        let (mut ffi_array, schema) = array.into_ffi();
+        ffi_array.offset = 1;
+        ffi_array.length = 3;
+
+        let imported =
+            unsafe { ImportedStringViewArray::try_from_ffi(ffi_array, &schema) }.unwrap();
+        assert_eq!(imported.len(), 3);
+        assert_eq!(imported.iter().flatten().collect::<Vec<_>>(), strings[1..]);
+        assert_eq!(imported.view(0), unsafe {
+            &*views.add(1).cast::<[u8; 16]>()
+        });
+    }
+
+    #[test]
+    fn invalid_import_test() {
+        let (array, mut schema) =
+            StringViewArray::from_iter([UmbraArcString::new("short")]).into_ffi();
+        schema.format = c"u".as_ptr();
+        assert_eq!(
+            unsafe { ImportedStringViewArray::try_from_ffi(array, &schema) }.unwrap_err(),
+            UmbraError::InvalidArray {
+                reason: "format is not utf8_view"
+            }
+        );
+
+        let views = vec![u128::from(LONG.len() as u32)];
+        let array = StringViewArray {
+            views,
+            buffers: Vec::new(),
+        };
+        let (array, schema) = array.into_ffi();
+        assert_eq!(
+            unsafe { ImportedStringViewArray::try_from_ffi(array, &schema) }.unwrap_err(),
+            UmbraError::InvalidView { index: 0 }
+        );
+    }
+}
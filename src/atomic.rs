@@ -0,0 +1,375 @@
+//! A cell holding an UmbraArcString which threads can load and replace concurrently without locks, in the spirit of
+//! arc-swap's ArcSwap.
+//!
+//! The string is published in an immutable boxed node, and the cell is a single word holding the node's address and,
+//! in the 16 bits above it, the number of loads reading the node. A load finds the node and adds itself to that count
+//! in a single atomic add, so no writer can free the node between the two. Having cloned the string, it takes itself
+//! off the count again, or if a writer replaced the node in the meantime, off the node's own count, which is
+//! where the writer moved the count it swapped out. Whoever brings the node's count to zero once both have happened
+//! frees the node. This is the split reference count of folly's AtomicSharedPtr.
+//!
+//! Neither loads nor writers wait on each other. A load retries taking itself off the count only when another load or
+//! a writer changed the word, and compare_and_swap retries only when a load did. The one exception is a load finding
+//! 32768 others in the count, which yields until some finish rather than overflow it. Cloning an inline string copies
+//! it out of the node without touching any other allocation.
+
+use std::{
+    fmt,
+    mem::ManuallyDrop,
+    ptr,
+    sync::atomic::Ordering::{AcqRel, Acquire, Relaxed, Release},
+};
+
+use crate::{
+    arc::UmbraArcString,
+    sync::{self, AtomicIsize, AtomicUsize},
+};
+
+/// Addresses fit in the low 48 bits on the platforms we support, the count of loads goes above them
+const COUNT_SHIFT: u32 = 48;
+const ADDR_MASK: usize = (1 << COUNT_SHIFT) - 1;
+const ONE_LOAD: usize = 1 << COUNT_SHIFT;
+/// Loads in the count past which new ones wait for some to finish, well short of overflowing the word
+const MAX_LOADS: usize = 1 << 15;
+
+struct Node {
+    /// Loads handed over by the writer which replaced this node, less those which finished after it did. Goes negative
+    /// while loads finish before the writer hands them over
+    count: AtomicIsize,
+    val: UmbraArcString,
+}
+
+/// The address of the node in a word
+fn node(word: usize) -> *mut Node {
+    ptr::with_exposed_provenance_mut(word & ADDR_MASK)
+}
+
+/// Loads in a word
+fn loads(word: usize) -> isize {
+    (word >> COUNT_SHIFT) as isize
+}
+
+/// Boxes a string into a node, giving the word publishing it with no loads
+fn publish(val: UmbraArcString) -> usize {
+    let node = Box::new(Node {
+        count: AtomicIsize::new(0),
+        val,
+    });
+    assert!(
+        ptr::from_ref(&*node).addr() <= ADDR_MASK,
+        "address doesn't fit in 48 bits"
+    );
+    Box::into_raw(node).expose_provenance()
+}
+
+/// SAFETY: node must be one that was replaced and whose count is still above zero, or that loads keep alive
+unsafe fn release(node: *mut Node, loads: isize) {
+    // AcqRel so whichever brings the count to zero frees the node after every load is done reading it
+    // SAFETY: the node is alive under preconditions
+    if unsafe { &(*node).count }.fetch_add(loads, AcqRel) + loads == 0 {
+        // SAFETY: nothing is left reading the node, and it was boxed by publish
+        drop(unsafe { Box::from_raw(node) });
+    }
+}
+
+/// A shared UmbraArcString which threads can load and replace concurrently
+pub struct AtomicUmbraString {
+    /// The address of the current node, and the loads reading it above COUNT_SHIFT
+    word: AtomicUsize,
+}
+
+impl AtomicUmbraString {
+    pub fn new(val: UmbraArcString) -> Self {
+        AtomicUmbraString {
+            word: AtomicUsize::new(publish(val)),
+        }
+    }
+
+    /// A clone of the current string
+    pub fn load(&self) -> UmbraArcString {
+        let word = self.enter();
+        // SAFETY: the load counted in word keeps the node alive until it leaves
+        let val = unsafe { &(*node(word)).val }.clone();
+        // SAFETY: word is from enter
+        unsafe { self.leave(word) };
+        val
+    }
+
+    /// Replaces the string, dropping the old one
+    pub fn store(&self, val: UmbraArcString) {
+        drop(self.swap(val));
+    }
+
+    /// Replaces the string, returning the old one
+    pub fn swap(&self, val: UmbraArcString) -> UmbraArcString {
+        let old = self.word.swap(publish(val), AcqRel);
+        let node = node(old);
+        // SAFETY: the node's count can't reach zero until the loads swapped out with it are handed over
+        let val = unsafe { &(*node).val }.clone();
+        // SAFETY: the node was replaced and its count is at most zero
+        unsafe { release(node, loads(old)) };
+        val
+    }
+
+    /// Replaces the string with new if it is still current, returning the replaced string, or giving back new if it
+    /// wasn't. Out-of-line strings are current if they are the same string or a clone of it, not just equal bytes
+    pub fn compare_and_swap(
+        &self,
+        current: &UmbraArcString,
+        new: UmbraArcString,
+    ) -> Result<UmbraArcString, UmbraArcString> {
+        // entered like a load, so the node compared stays alive and can't be replaced by one at the same address
+        let entered = self.enter();
+        // SAFETY: the load counted in entered keeps the node alive
+        let val = unsafe { &(*node(entered)).val };
+        if val.raw_words() != current.raw_words() {
+            // SAFETY: entered is from enter
+            unsafe { self.leave(entered) };
+            return Err(new);
+        }
+
+        let new = publish(new);
+        let mut word = self.word.load(Relaxed);
+        while node(word) == node(entered) {
+            match self.word.compare_exchange_weak(word, new, AcqRel, Relaxed) {
+                Ok(_) => {
+                    let val = val.clone();
+                    // SAFETY: the node was replaced, and the count handed over includes this one's load, so it stays
+                    // alive until now
+                    unsafe { release(node(word), loads(word) - 1) };
+                    return Ok(val);
+                }
+                Err(changed) => word = changed,
+            }
+        }
+
+        // SAFETY: entered is from enter
+        unsafe { self.leave(entered) };
+        // SAFETY: new was never published, so this still owns it
+        Err(unsafe { Box::from_raw(node(new)) }.val)
+    }
+
+    pub fn into_inner(self) -> UmbraArcString {
+        let this = ManuallyDrop::new(self);
+        // SAFETY: no load can be using the node as this is owned, and the cell is never dropped
+        unsafe { Box::from_raw(node(this.word.load(Acquire))) }.val
+    }
+
+    /// Counts a load of the current node in the word, giving the word it counted itself in
+    fn enter(&self) -> usize {
+        loop {
+            let word = self.word.fetch_add(ONE_LOAD, Acquire);
+            if (word >> COUNT_SHIFT) < MAX_LOADS {
+                return word;
+            }
+            // SAFETY: word is as entered
+            unsafe { self.leave(word) };
+            sync::yield_now();
+        }
+    }
+
+    /// Takes a load off the count of the word it entered, or off its node's count if a writer has replaced it and
+    /// handed the load over
+    ///
+    /// SAFETY: entered must be from enter, and be left once
+    unsafe fn leave(&self, entered: usize) {
+        let mut word = self.word.load(Relaxed);
+        // the node can't be freed while counted, so one at the same address is still the one entered
+        while node(word) == node(entered) {
+            // Release so a writer replacing the node after this sees the load done reading it
+            match self
+                .word
+                .compare_exchange_weak(word, word - ONE_LOAD, Release, Relaxed)
+            {
+                Ok(_) => return,
+                Err(changed) => word = changed,
+            }
+        }
+        // SAFETY: the node was replaced, and this load is counted in its count or still to be handed over
+        unsafe { release(node(entered), -1) };
+    }
+}
+
+impl Default for AtomicUmbraString {
+    fn default() -> Self {
+        Self::new(UmbraArcString::new(""))
+    }
+}
+
+impl From<UmbraArcString> for AtomicUmbraString {
+    fn from(value: UmbraArcString) -> Self {
+        Self::new(value)
+    }
+}
+
+impl fmt::Debug for AtomicUmbraString {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(&self.load(), f)
+    }
+}
+
+impl Drop for AtomicUmbraString {
+    fn drop(&mut self) {
+        // SAFETY: &mut self means no load is using the node
+        drop(unsafe { Box::from_raw(node(self.word.load(Acquire))) });
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{sync::Arc, thread};
+
+    use super::AtomicUmbraString;
+    use crate::arc::UmbraArcString;
+
+    #[test]
+    fn swap_test() {
+        let cell = AtomicUmbraString::new(UmbraArcString::new("inline"));
+        assert_eq!(cell.load(), "inline");
+
+        let long = UmbraArcString::new("a label long enough for the heap");
+        assert_eq!(cell.swap(long.clone()), "inline");
+        assert_eq!(cell.load(), long);
+        assert_eq!(long.strong_count(), Some(2));
+
+        cell.store(UmbraArcString::new("short"));
+        assert_eq!(long.strong_count(), Some(1));
+        assert_eq!(cell.into_inner(), "short");
+    }
+
+    #[test]
+    fn compare_and_swap_test() {
+        let long = UmbraArcString::new("a label long enough for the heap");
+        let cell = AtomicUmbraString::new(long.clone());
+
+        let equal = UmbraArcString::new("a label long enough for the heap");
+        let new = UmbraArcString::new("new");
+        let new = cell.compare_and_swap(&equal, new).unwrap_err();
+        assert_eq!(cell.compare_and_swap(&long, new).unwrap(), long);
+        assert_eq!(cell.load(), "new");
+
+        // inline strings are compared by value
+        let replaced = cell.compare_and_swap(&UmbraArcString::new("new"), long.clone());
+        assert_eq!(replaced.unwrap(), "new");
+    }
+
+    #[test]
+    fn threads_test() {
+        let values = [
+            "short",
+            "a label long enough for the heap",
+            "another label which lives on the heap",
+        ]
+        .map(UmbraArcString::new);
+        let cell = Arc::new(AtomicUmbraString::new(values[0].clone()));
+
+        let readers: Vec<_> = (0..4)
+            .map(|_| {
+                let (cell, values) = (cell.clone(), values.clone());
+                thread::spawn(move || {
+                    for _ in 0..10_000 {
+                        assert!(values.contains(&cell.load()));
+                    }
+                })
+            })
+            .collect();
+        for i in 0..1000 {
+            cell.store(values[i % values.len()].clone());
+        }
+        for reader in readers {
+            reader.join().unwrap();
+        }
+
+        drop(cell);
+        assert!(values
+            .iter()
+            .all(|val| val.strong_count().unwrap_or(1) == 1));
+    }
+
+    #[test]
+    fn compare_and_swap_threads_test() {
+        let values = [
+            "a label long enough for the heap",
+            "another label which lives on the heap",
+            "a third label which lives on the heap",
+        ]
+        .map(UmbraArcString::new);
+        let cell = Arc::new(AtomicUmbraString::new(values[0].clone()));
+
+        // each successful compare_and_swap moves the cell on by one value
+        let writers: Vec<_> = (0..4)
+            .map(|_| {
+                let (cell, values) = (cell.clone(), values.clone());
+                thread::spawn(move || {
+                    for _ in 0..1000 {
+                        let mut current = cell.load();
+                        loop {
+                            let i = values.iter().position(|val| *val == current).unwrap();
+                            let next = values[(i + 1) % values.len()].clone();
+                            match cell.compare_and_swap(&current, next) {
+                                Ok(replaced) => {
+                                    assert_eq!(replaced, current);
+                                    break;
+                                }
+                                Err(_) => current = cell.load(),
+                            }
+                        }
+                    }
+                })
+            })
+            .collect();
+        for writer in writers {
+            writer.join().unwrap();
+        }
+        assert_eq!(cell.load(), values[4000 % values.len()]);
+
+        drop(cell);
+        assert!(values.iter().all(|val| val.strong_count() == Some(1)));
+    }
+}
+
+/// Model tests of loads racing writers, see the loom_test module of arc
+#[cfg(all(test, loom))]
+mod loom_test {
+    use loom::{sync::Arc, thread};
+
+    use super::AtomicUmbraString;
+    use crate::arc::UmbraArcString;
+
+    #[test]
+    fn load_swap_test() {
+        loom::model(|| {
+            let old = "a label long enough for the heap";
+            let new = "another label which lives on the heap";
+            let cell = Arc::new(AtomicUmbraString::new(UmbraArcString::new(old)));
+
+            let reader = {
+                let cell = cell.clone();
+                thread::spawn(move || cell.load())
+            };
+            drop(cell.swap(UmbraArcString::new(new)));
+            let loaded = reader.join().unwrap();
+            assert!(loaded == old || loaded == new);
+            assert_eq!(cell.load(), new);
+        });
+    }
+
+    #[test]
+    fn load_compare_and_swap_test() {
+        loom::model(|| {
+            let old = UmbraArcString::new("a label long enough for the heap");
+            let cell = Arc::new(AtomicUmbraString::new(old.clone()));
+
+            let reader = {
+                let cell = cell.clone();
+                thread::spawn(move || cell.load())
+            };
+            let new = UmbraArcString::new("another label which lives on the heap");
+            assert_eq!(cell.compare_and_swap(&old, new.clone()).unwrap(), old);
+            let loaded = reader.join().unwrap();
+            assert!(loaded == old || loaded == new);
+            drop(loaded);
+            assert_eq!(old.strong_count(), Some(1));
+        });
+    }
+}
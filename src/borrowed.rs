@@ -0,0 +1,222 @@
+use core::str;
+use std::{marker::PhantomData, ops::Deref, ptr};
+
+use crate::{
+    arc::{UmbraArcString, MAX_INLINE},
+    common::{self, impl_umbra_str_traits, UmbraLayout},
+    UmbraError,
+};
+
+/// A borrowed Umbra-style string, with the same layout as the owned types but pointing into a buffer it doesn't own
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct UmbraStr<'a> {
+    len: u32,
+    prefix: [u8; 4],
+    extra: UmbraStrExtra,
+    _marker: PhantomData<&'a str>,
+}
+
+#[derive(Clone, Copy)]
+pub union UmbraStrExtra {
+    data: [u8; 8],
+    ptr: *const u8,
+}
+
+// SAFETY: UmbraStr is only ever a copy of some inline bytes or a shared borrow of a str, so behaves like &'a str
+unsafe impl Send for UmbraStr<'_> {}
+// SAFETY: as above
+unsafe impl Sync for UmbraStr<'_> {}
+
+impl<'a> UmbraStr<'a> {
+    /// Panics if the string is longer than u32::MAX bytes
+    pub fn new(val: &'a str) -> UmbraStr<'a> {
+        common::unwrap_new(Self::try_new(val))
+    }
+
+    pub fn try_new(val: &'a str) -> Result<UmbraStr<'a>, UmbraError> {
+        let len = common::checked_len(val.len())?;
+
+        Ok(if len as usize <= MAX_INLINE {
+            let (prefix, extra) = common::split_inline(val.as_bytes());
+
+            UmbraStr {
+                len,
+                prefix,
+                extra: UmbraStrExtra { data: extra },
+                _marker: PhantomData,
+            }
+        } else {
+            UmbraStr {
+                len,
+                prefix: common::heap_prefix(val.as_bytes()),
+                extra: UmbraStrExtra { ptr: val.as_ptr() },
+                _marker: PhantomData,
+            }
+        })
+    }
+
+    /// Builds a view of an inline string from the parts of another Umbra string type
+    ///
+    /// SAFETY: len must be at most MAX_INLINE and prefix followed by data must start with len bytes of valid utf-8
+    pub(crate) unsafe fn from_inline_parts(len: u32, prefix: [u8; 4], data: [u8; 8]) -> Self {
+        debug_assert!(len as usize <= MAX_INLINE);
+        UmbraStr {
+            len,
+            prefix,
+            extra: UmbraStrExtra { data },
+            _marker: PhantomData,
+        }
+    }
+
+    /// Builds a view of an out-of-line string from the parts of another Umbra string type
+    ///
+    /// SAFETY: len must be over MAX_INLINE, and ptr must point to len bytes of valid utf-8 starting with prefix,
+    /// which live for 'a
+    pub(crate) unsafe fn from_ptr_parts(len: u32, prefix: [u8; 4], ptr: *const u8) -> Self {
+        debug_assert!(len as usize > MAX_INLINE);
+        UmbraStr {
+            len,
+            prefix,
+            extra: UmbraStrExtra { ptr },
+            _marker: PhantomData,
+        }
+    }
+
+    pub fn is_inline(&self) -> bool {
+        self.len <= MAX_INLINE as u32
+    }
+
+    pub fn len(&self) -> usize {
+        self.len as usize
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Copies into an owned string, this only allocates if the string isn't inline
+    pub fn to_owned(&self) -> UmbraArcString {
+        UmbraArcString::new(&**self)
+    }
+}
+
+impl UmbraLayout for UmbraStr<'_> {
+    #[inline]
+    fn raw_len(&self) -> u32 {
+        self.len
+    }
+
+    #[inline]
+    fn raw_prefix(&self) -> [u8; 4] {
+        self.prefix
+    }
+
+    #[inline]
+    fn inline_data(&self) -> Option<&[u8; 8]> {
+        if self.is_inline() {
+            // SAFETY: is_inline() so data is active
+            Some(unsafe { &self.extra.data })
+        } else {
+            None
+        }
+    }
+
+    #[inline]
+    fn suffix_bytes(&self) -> &[u8] {
+        self.as_bytes().get(4..).unwrap_or_default()
+    }
+}
+
+impl Deref for UmbraStr<'_> {
+    type Target = str;
+
+    fn deref(&self) -> &Self::Target {
+        let bytes = if self.is_inline() {
+            // SAFETY: UmbraStr has the Umbra layout and is_inline() so data is active
+            unsafe { common::inline_bytes(self, self.len) }
+        } else {
+            // SAFETY: !is_inline() so ptr is active, and points to len bytes which live for 'a
+            unsafe { &*ptr::slice_from_raw_parts(self.extra.ptr, self.len as usize) }
+        };
+        // SAFETY: bytes were borrowed or copied from a str, so should be valid utf-8
+        unsafe { str::from_utf8_unchecked(bytes) }
+    }
+}
+
+impl_umbra_str_traits!(UmbraStr<'_>);
+
+impl<'a> From<&'a str> for UmbraStr<'a> {
+    fn from(value: &'a str) -> Self {
+        UmbraStr::new(value)
+    }
+}
+
+impl<'a> From<&'a UmbraArcString> for UmbraStr<'a> {
+    fn from(value: &'a UmbraArcString) -> Self {
+        value.as_umbra_str()
+    }
+}
+
+impl PartialEq<UmbraArcString> for UmbraStr<'_> {
+    fn eq(&self, other: &UmbraArcString) -> bool {
+        common::umbra_eq(self, other)
+    }
+}
+
+impl PartialEq<UmbraStr<'_>> for UmbraArcString {
+    fn eq(&self, other: &UmbraStr<'_>) -> bool {
+        common::umbra_eq(self, other)
+    }
+}
+
+impl PartialOrd<UmbraArcString> for UmbraStr<'_> {
+    fn partial_cmp(&self, other: &UmbraArcString) -> Option<std::cmp::Ordering> {
+        Some(common::umbra_cmp(self, other))
+    }
+}
+
+impl PartialOrd<UmbraStr<'_>> for UmbraArcString {
+    fn partial_cmp(&self, other: &UmbraStr<'_>) -> Option<std::cmp::Ordering> {
+        Some(common::umbra_cmp(self, other))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::UmbraStr;
+    use crate::arc::UmbraArcString;
+
+    #[test]
+    fn borrow_test() {
+        let page = String::from("abcdefghijklmnopqrstuvwxyz");
+        let inline = UmbraStr::new(&page[..10]);
+        let heap = UmbraStr::new(&page[2..20]);
+
+        assert_eq!(size_of::<UmbraStr>(), 16);
+        assert_eq!(inline, "abcdefghij");
+        assert_eq!(heap, "cdefghijklmnopqrst");
+        assert!(inline < heap);
+    }
+
+    #[test]
+    fn arc_comparison_test() {
+        let owned = UmbraArcString::new("abcdefghijklmnopqr");
+        let page = String::from("abcdefghijklmnopqrs");
+
+        assert_eq!(owned.as_umbra_str(), owned);
+        assert_eq!(UmbraStr::new(&page[..18]), owned);
+        assert!(owned < UmbraStr::new(&page));
+        assert!(UmbraStr::new(&page) > owned);
+    }
+
+    #[test]
+    fn to_owned_test() {
+        let page = String::from("abcdefghijklmnopqrstuvwxyz");
+        let owned = UmbraStr::new(&page[..20]).to_owned();
+        drop(page);
+
+        assert_eq!(owned, "abcdefghijklmnopqrst");
+        assert_eq!(UmbraStr::new("short").to_owned(), "short");
+    }
+}
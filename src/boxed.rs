@@ -0,0 +1,366 @@
+use core::str;
+use std::{alloc, mem::ManuallyDrop, ops::Deref, ptr};
+
+use crate::{
+    arc::{UmbraArcString, MAX_INLINE},
+    common::{self, impl_umbra_str_traits, UmbraLayout, UmbraStrMut, HEADER_SIZE, RESERVED_WORDS},
+};
+
+/// An owned Umbra-style string with a uniquely owned heap buffer, which can be mutated and grown in place
+#[repr(C)]
+pub struct UmbraBoxString {
+    len: u32,
+    prefix: [u8; 4],
+    extra: UmbraBoxExtra,
+}
+
+pub union UmbraBoxExtra {
+    data: [u8; 8],
+    ptr: *mut u8,
+}
+
+/// Header at the start of every out-of-line allocation, followed by cap bytes of which the first len are in use
+#[repr(C)]
+struct BoxHeader {
+    cap: usize,
+    /// Pads the header to HEADER_SIZE, UmbraArcString keeps its weak count and the allocation length here
+    _reserved: [usize; RESERVED_WORDS],
+}
+
+const _: () = assert!(size_of::<BoxHeader>() == HEADER_SIZE);
+
+// SAFETY: UmbraBoxString behaves like Box<str>. Inline strings are plain bytes, and the heap buffer is uniquely owned,
+// only written through &mut self and freed when the string is dropped, so moving it to another thread moves sole
+// ownership of the buffer with it
+unsafe impl Send for UmbraBoxString {}
+// SAFETY: as above, &UmbraBoxString only gives out shared access to the bytes
+unsafe impl Sync for UmbraBoxString {}
+
+impl UmbraBoxString {
+    /// Panics if the string is longer than u32::MAX bytes
+    pub fn new(val: impl AsRef<str>) -> UmbraBoxString {
+        let val_str = val.as_ref();
+        let len = common::unwrap_new(common::checked_len(val_str.len()));
+
+        if len as usize <= MAX_INLINE {
+            let (prefix, extra) = common::split_inline(val_str.as_bytes());
+
+            UmbraBoxString {
+                len,
+                prefix,
+                extra: UmbraBoxExtra { data: extra },
+            }
+        } else {
+            UmbraBoxString {
+                len,
+                prefix: common::heap_prefix(val_str.as_bytes()),
+                extra: UmbraBoxExtra::inner_ptr_new(val_str.as_bytes(), len as usize),
+            }
+        }
+    }
+
+    pub fn is_inline(&self) -> bool {
+        self.len <= MAX_INLINE as u32
+    }
+
+    pub fn len(&self) -> usize {
+        self.len as usize
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// The number of bytes the string can hold without reallocating
+    pub fn capacity(&self) -> usize {
+        if self.is_inline() {
+            MAX_INLINE
+        } else {
+            // SAFETY: !is_inline() so ptr is active
+            unsafe { self.extra.inner_ptr_cap() }
+        }
+    }
+
+    /// Mutable access to the contents, the prefix is updated when the returned guard is dropped
+    pub fn as_mut_str(&mut self) -> UmbraStrMut<'_> {
+        let len = self.len;
+        if self.is_inline() {
+            // SAFETY: UmbraBoxString has the Umbra layout and is_inline() so data is active
+            let bytes = unsafe { common::inline_bytes_mut(self, len) };
+            // SAFETY: bytes were copied from a str, so should be valid utf-8
+            UmbraStrMut::new(None, unsafe { str::from_utf8_unchecked_mut(bytes) })
+        } else {
+            // SAFETY: !is_inline() so ptr is active, the bytes are uniquely owned by self and don't overlap prefix
+            let bytes = unsafe { self.extra.inner_ptr_bytes_mut(len) };
+            // SAFETY: bytes were copied from a str, so should be valid utf-8
+            UmbraStrMut::new(Some(&mut self.prefix), unsafe {
+                str::from_utf8_unchecked_mut(bytes)
+            })
+        }
+    }
+
+    pub fn push_str(&mut self, val: &str) {
+        let old_len = self.len();
+        let new_len = common::unwrap_new(common::checked_len(old_len + val.len())) as usize;
+
+        if new_len <= MAX_INLINE {
+            // SAFETY: UmbraBoxString has the Umbra layout and is_inline() so data is active, new_len still fits inline
+            let bytes = unsafe { common::inline_bytes_mut(self, new_len as u32) };
+            bytes[old_len..].copy_from_slice(val.as_bytes());
+        } else if self.is_inline() {
+            let mut contents = Vec::with_capacity(new_len);
+            contents.extend_from_slice(self.as_bytes());
+            contents.extend_from_slice(val.as_bytes());
+
+            self.prefix = common::heap_prefix(&contents);
+            self.extra = UmbraBoxExtra::inner_ptr_new(&contents, new_len.max(2 * MAX_INLINE));
+        } else {
+            // SAFETY: !is_inline() so ptr is active
+            let cap = unsafe { self.extra.inner_ptr_cap() };
+            if new_len > cap {
+                // the capacity stays within u32 so into_arc can record it in UmbraArcString's header
+                let new_cap = new_len.max(2 * cap).min(u32::MAX as usize);
+                // SAFETY: !is_inline() so ptr is active
+                unsafe { self.extra.inner_ptr_resize(new_cap) };
+            }
+            // SAFETY: ptr is active and now has room for new_len bytes after the header
+            unsafe {
+                ptr::copy_nonoverlapping(
+                    val.as_ptr(),
+                    self.extra.ptr.add(HEADER_SIZE + old_len),
+                    val.len(),
+                )
+            };
+        }
+
+        self.len = new_len as u32;
+    }
+
+    /// Converts into a reference counted string, reusing the heap buffer rather than copying it. Any spare capacity stays
+    /// allocated until the last reference is dropped
+    pub fn into_arc(self) -> UmbraArcString {
+        if self.is_inline() {
+            return UmbraArcString::new(&*self);
+        }
+
+        let this = ManuallyDrop::new(self);
+        // SAFETY: !is_inline() so ptr is active
+        let (ptr, cap) = unsafe { (this.extra.ptr, this.extra.inner_ptr_cap()) };
+        let cap = u32::try_from(cap).expect("capacity is kept within u32");
+
+        // SAFETY: the allocation has room for cap bytes, the first len of which were copied from strs, and this is no
+        // longer dropped so owns it
+        unsafe { UmbraArcString::from_unique_heap(this.len, this.prefix, ptr, cap) }
+    }
+}
+
+impl From<UmbraBoxString> for UmbraArcString {
+    fn from(value: UmbraBoxString) -> Self {
+        value.into_arc()
+    }
+}
+
+impl UmbraLayout for UmbraBoxString {
+    #[inline]
+    fn raw_len(&self) -> u32 {
+        self.len
+    }
+
+    #[inline]
+    fn raw_prefix(&self) -> [u8; 4] {
+        self.prefix
+    }
+
+    #[inline]
+    fn inline_data(&self) -> Option<&[u8; 8]> {
+        if self.is_inline() {
+            // SAFETY: is_inline() so data is active
+            Some(unsafe { &self.extra.data })
+        } else {
+            None
+        }
+    }
+
+    #[inline]
+    fn suffix_bytes(&self) -> &[u8] {
+        self.as_bytes().get(4..).unwrap_or_default()
+    }
+}
+
+impl Clone for UmbraBoxString {
+    fn clone(&self) -> Self {
+        UmbraBoxString::new(&**self)
+    }
+}
+
+impl Deref for UmbraBoxString {
+    type Target = str;
+
+    fn deref(&self) -> &Self::Target {
+        let bytes = if self.is_inline() {
+            // SAFETY: UmbraBoxString has the Umbra layout and is_inline() so data is active
+            unsafe { common::inline_bytes(self, self.len) }
+        } else {
+            // SAFETY: !is_inline() so ptr is active
+            unsafe { self.extra.inner_ptr_bytes(self.len) }
+        };
+        // SAFETY: bytes were copied from a str, so should be valid utf-8
+        unsafe { str::from_utf8_unchecked(bytes) }
+    }
+}
+
+impl_umbra_str_traits!(UmbraBoxString);
+
+impl Drop for UmbraBoxString {
+    fn drop(&mut self) {
+        if !self.is_inline() {
+            // SAFETY: !is_inline() so ptr is active, ptr is private and created with inner_ptr_new
+            unsafe { self.extra.inner_ptr_drop() }
+        }
+    }
+}
+
+impl UmbraBoxExtra {
+    /// Allocates room for cap bytes and copies val into the start
+    fn inner_ptr_new(val: &[u8], cap: usize) -> Self {
+        debug_assert!(val.len() <= cap);
+        let ptr = common::heap_alloc(cap);
+
+        // SAFETY: ptr was just allocated with room for the header followed by cap bytes
+        unsafe {
+            ptr.cast::<BoxHeader>().write(BoxHeader {
+                cap,
+                _reserved: [0; RESERVED_WORDS],
+            });
+            ptr::copy_nonoverlapping(val.as_ptr(), ptr.add(HEADER_SIZE), val.len());
+        }
+
+        Self { ptr }
+    }
+
+    /// SAFETY: Must be called with ptr field active
+    unsafe fn inner_ptr_cap(&self) -> usize {
+        // SAFETY: ptr must be active under preconditions and points to a header
+        unsafe { (*self.ptr.cast::<BoxHeader>()).cap }
+    }
+
+    /// SAFETY: Must be called with ptr field active and with the current length of the string
+    unsafe fn inner_ptr_bytes(&self, len: u32) -> &[u8] {
+        // SAFETY: ptr must be active under preconditions, the bytes follow the header and live as long as self
+        unsafe { &*ptr::slice_from_raw_parts(self.ptr.add(HEADER_SIZE), len as usize) }
+    }
+
+    /// SAFETY: Must be called with ptr field active and with the current length of the string
+    unsafe fn inner_ptr_bytes_mut(&mut self, len: u32) -> &mut [u8] {
+        // SAFETY: as in inner_ptr_bytes, and the buffer is uniquely owned by self
+        unsafe { &mut *ptr::slice_from_raw_parts_mut(self.ptr.add(HEADER_SIZE), len as usize) }
+    }
+
+    /// Reallocates the buffer to hold exactly new_cap bytes
+    ///
+    /// SAFETY: Must be called with ptr field active and new_cap at least the current length of the string
+    unsafe fn inner_ptr_resize(&mut self, new_cap: usize) {
+        // SAFETY: ptr must be active under preconditions
+        let (ptr, old_cap) = unsafe { (self.ptr, self.inner_ptr_cap()) };
+        let new_size = common::heap_layout(new_cap).size();
+
+        // SAFETY: ptr was allocated with the layout for old_cap, and the new size is non-zero as it contains the header
+        let new_ptr = unsafe { alloc::realloc(ptr, common::heap_layout(old_cap), new_size) };
+        if new_ptr.is_null() {
+            alloc::handle_alloc_error(common::heap_layout(new_cap));
+        }
+
+        // SAFETY: new_ptr was just allocated with room for the header
+        unsafe {
+            new_ptr.cast::<BoxHeader>().write(BoxHeader {
+                cap: new_cap,
+                _reserved: [0; RESERVED_WORDS],
+            })
+        };
+        self.ptr = new_ptr;
+    }
+
+    /// SAFETY: Must be called with ptr field active and it containing a pointer from inner_ptr_new
+    unsafe fn inner_ptr_drop(&self) {
+        // SAFETY: ptr must be active under preconditions
+        let (ptr, cap) = unsafe { (self.ptr, self.inner_ptr_cap()) };
+
+        // SAFETY: the allocation is uniquely owned and was made with the layout for cap
+        unsafe { alloc::dealloc(ptr, common::heap_layout(cap)) }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::UmbraBoxString;
+    use crate::{arc::UmbraArcString, rc::UmbraRcString};
+
+    #[test]
+    fn push_str_test() {
+        let mut umbra = UmbraBoxString::new("ab");
+        umbra.push_str("cdefgh");
+        assert!(umbra.is_inline());
+        assert_eq!(umbra, "abcdefgh");
+
+        umbra.push_str("ijklmnop");
+        assert!(!umbra.is_inline());
+        assert_eq!(umbra, "abcdefghijklmnop");
+
+        for _ in 0..10 {
+            umbra.push_str("qrstuvwxyz");
+        }
+        assert_eq!(umbra.len(), 116);
+        assert!(umbra.ends_with("qrstuvwxyz"));
+    }
+
+    #[test]
+    fn as_mut_str_test() {
+        let mut inline = UmbraBoxString::new("abc");
+        inline.as_mut_str().make_ascii_uppercase();
+        assert_eq!(inline, "ABC");
+
+        let mut heap = UmbraBoxString::new("abcdefghijklmnop");
+        heap.as_mut_str().make_ascii_uppercase();
+        assert_eq!(heap, UmbraBoxString::new("ABCDEFGHIJKLMNOP"));
+        assert!(heap < UmbraBoxString::new("abcdefghijklmnop"));
+    }
+
+    #[test]
+    fn clone_is_deep_test() {
+        let mut umbra = UmbraBoxString::new("abcdefghijklmnop");
+        let cloned = umbra.clone();
+        umbra.as_mut_str().make_ascii_uppercase();
+
+        assert_eq!(cloned, "abcdefghijklmnop");
+        assert_eq!(umbra, "ABCDEFGHIJKLMNOP");
+    }
+
+    #[test]
+    fn into_arc_test() {
+        let mut umbra = UmbraBoxString::new("abcdefghijklm");
+        umbra.push_str("nopqrstuvwxyz");
+        let arc: UmbraArcString = umbra.into();
+        let cloned = arc.clone();
+        drop(arc);
+
+        assert_eq!(cloned, "abcdefghijklmnopqrstuvwxyz");
+        assert_eq!(UmbraBoxString::new("short").into_arc(), "short");
+    }
+
+    #[test]
+    fn into_arc_spare_capacity_test() {
+        let mut umbra = UmbraBoxString::new("a string that lives on the heap");
+        umbra.push_str(", and grows");
+        assert!(umbra.capacity() > umbra.len());
+        let bytes = umbra.as_ptr();
+
+        let arc = umbra.into_arc();
+        assert_eq!(arc.as_ptr(), bytes);
+        assert_eq!(arc, "a string that lives on the heap, and grows");
+        // the buffer holds more than the string, so can't be handed over to a type which frees it with the length
+        let arc = UmbraRcString::try_from_arc(arc).unwrap_err();
+        let weak = arc.downgrade();
+        drop(arc);
+        assert!(weak.upgrade().is_none());
+    }
+}
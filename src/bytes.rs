@@ -0,0 +1,272 @@
+use std::{
+    cmp::Ordering,
+    fmt::{self, Debug, Display},
+    hash::Hash,
+    mem::ManuallyDrop,
+    ops::Deref,
+    ptr,
+    str::{self, Utf8Error},
+};
+
+use crate::{
+    arc::{UmbraArcExtra, UmbraArcString, MAX_INLINE},
+    common::{self, UmbraLayout},
+    UmbraError,
+};
+
+/// An owned Atomically reference counted Umbra-style byte string, which doesn't have to be valid utf-8
+///
+/// This uses the same representation as UmbraArcString, so the two convert between each other without copying
+#[repr(C)]
+pub struct UmbraArcBytes {
+    len: u32,
+    prefix: [u8; 4],
+    extra: UmbraArcExtra,
+}
+
+// SAFETY: the same as for UmbraArcString, which this shares its representation and refcounting with
+unsafe impl Send for UmbraArcBytes {}
+// SAFETY: as above
+unsafe impl Sync for UmbraArcBytes {}
+
+impl UmbraArcBytes {
+    /// Panics if the string is longer than u32::MAX bytes
+    pub fn new(val: impl AsRef<[u8]>) -> UmbraArcBytes {
+        common::unwrap_new(Self::try_new(val))
+    }
+
+    pub fn try_new(val: impl AsRef<[u8]>) -> Result<UmbraArcBytes, UmbraError> {
+        let val = val.as_ref();
+        let len = common::checked_len(val.len())?;
+
+        if len as usize <= MAX_INLINE {
+            let (prefix, extra) = common::split_inline(val);
+
+            Ok(UmbraArcBytes {
+                len,
+                prefix,
+                extra: UmbraArcExtra::inline(extra),
+            })
+        } else {
+            Ok(UmbraArcBytes {
+                len,
+                prefix: common::heap_prefix(val),
+                extra: UmbraArcExtra::try_inner_ptr_new(val)?,
+            })
+        }
+    }
+
+    pub fn is_inline(&self) -> bool {
+        self.len <= MAX_INLINE as u32
+    }
+
+    pub fn len(&self) -> usize {
+        self.len as usize
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Converts into a string without copying, if the bytes are valid utf-8
+    pub fn into_string(self) -> Result<UmbraArcString, IntoStringError> {
+        if let Err(error) = str::from_utf8(&self) {
+            return Err(IntoStringError { bytes: self, error });
+        }
+
+        let this = ManuallyDrop::new(self);
+        // SAFETY: this is never used or dropped again, so extra is moved out exactly once
+        let extra = unsafe { ptr::read(&this.extra) };
+        // SAFETY: the parts use UmbraArcExtra the same way as UmbraArcString, and were just checked to be utf-8
+        Ok(unsafe { UmbraArcString::from_raw_parts(this.len, this.prefix, extra) })
+    }
+}
+
+/// The error from UmbraArcBytes::into_string, which gives back the bytes which weren't valid utf-8
+#[derive(Debug)]
+pub struct IntoStringError {
+    bytes: UmbraArcBytes,
+    error: Utf8Error,
+}
+
+impl IntoStringError {
+    pub fn into_bytes(self) -> UmbraArcBytes {
+        self.bytes
+    }
+
+    pub fn utf8_error(&self) -> Utf8Error {
+        self.error
+    }
+}
+
+impl Display for IntoStringError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        Display::fmt(&self.error, f)
+    }
+}
+
+impl std::error::Error for IntoStringError {}
+
+impl From<UmbraArcString> for UmbraArcBytes {
+    fn from(value: UmbraArcString) -> Self {
+        let (len, prefix, extra) = value.into_raw_parts();
+        UmbraArcBytes { len, prefix, extra }
+    }
+}
+
+impl TryFrom<UmbraArcBytes> for UmbraArcString {
+    type Error = IntoStringError;
+
+    fn try_from(value: UmbraArcBytes) -> Result<Self, Self::Error> {
+        value.into_string()
+    }
+}
+
+impl UmbraLayout for UmbraArcBytes {
+    #[inline]
+    fn raw_len(&self) -> u32 {
+        self.len
+    }
+
+    #[inline]
+    fn raw_prefix(&self) -> [u8; 4] {
+        self.prefix
+    }
+
+    #[inline]
+    fn inline_data(&self) -> Option<&[u8; 8]> {
+        if self.is_inline() {
+            // SAFETY: is_inline() so data is active
+            Some(unsafe { self.extra.inline_data() })
+        } else {
+            None
+        }
+    }
+
+    #[inline]
+    fn suffix_bytes(&self) -> &[u8] {
+        self.get(4..).unwrap_or_default()
+    }
+}
+
+impl Clone for UmbraArcBytes {
+    fn clone(&self) -> Self {
+        Self {
+            len: self.len,
+            prefix: self.prefix,
+            extra: if self.is_inline() {
+                // SAFETY: is_inline() so data is active
+                unsafe { self.extra.inner_data_clone() }
+            } else {
+                // SAFETY: !is_inline() so ptr is active
+                unsafe { self.extra.inner_ptr_clone() }
+            },
+        }
+    }
+}
+
+impl Deref for UmbraArcBytes {
+    type Target = [u8];
+
+    fn deref(&self) -> &Self::Target {
+        if self.is_inline() {
+            // SAFETY: UmbraArcBytes has the Umbra layout and is_inline() so data is active
+            unsafe { common::inline_bytes(self, self.len) }
+        } else {
+            // SAFETY: !is_inline() so ptr is active
+            unsafe { self.extra.inner_ptr_bytes(self.len) }
+        }
+    }
+}
+
+impl AsRef<[u8]> for UmbraArcBytes {
+    fn as_ref(&self) -> &[u8] {
+        self
+    }
+}
+
+impl Debug for UmbraArcBytes {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        Debug::fmt(&**self, f)
+    }
+}
+
+impl Hash for UmbraArcBytes {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        (**self).hash(state)
+    }
+}
+
+impl Eq for UmbraArcBytes {}
+
+impl PartialEq<UmbraArcBytes> for UmbraArcBytes {
+    fn eq(&self, other: &UmbraArcBytes) -> bool {
+        common::umbra_eq(self, other)
+    }
+}
+
+impl PartialEq<&[u8]> for UmbraArcBytes {
+    fn eq(&self, other: &&[u8]) -> bool {
+        **self == **other
+    }
+}
+
+impl Ord for UmbraArcBytes {
+    fn cmp(&self, other: &Self) -> Ordering {
+        common::umbra_cmp(self, other)
+    }
+}
+
+impl PartialOrd<UmbraArcBytes> for UmbraArcBytes {
+    fn partial_cmp(&self, other: &UmbraArcBytes) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialOrd<&[u8]> for UmbraArcBytes {
+    fn partial_cmp(&self, other: &&[u8]) -> Option<Ordering> {
+        Some((**self).cmp(*other))
+    }
+}
+
+impl Drop for UmbraArcBytes {
+    fn drop(&mut self) {
+        if !self.is_inline() {
+            // SAFETY: !is_inline() so ptr is active, ptr is private and created with inner_ptr_new
+            unsafe { self.extra.inner_ptr_drop() }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::UmbraArcBytes;
+    use crate::arc::UmbraArcString;
+
+    #[test]
+    fn basic_test() {
+        let inline = UmbraArcBytes::new([0xff, 0x00, 0xfe]);
+        let heap = UmbraArcBytes::new([0xffu8; 20]);
+
+        assert_eq!(inline, &[0xff, 0x00, 0xfe][..]);
+        assert_eq!(heap.len(), 20);
+        assert!(inline < heap);
+        assert_eq!(heap.clone(), heap);
+    }
+
+    #[test]
+    fn into_string_test() {
+        let bytes = UmbraArcBytes::new("a string that lives on the heap");
+        let shared = bytes.clone();
+        let string = bytes.into_string().unwrap();
+        drop(shared);
+
+        assert_eq!(string, "a string that lives on the heap");
+        assert_eq!(string.into_bytes(), &b"a string that lives on the heap"[..]);
+
+        let invalid = UmbraArcBytes::new(b"not utf-8 \xff\xff\xff\xff\xff");
+        let error = UmbraArcString::try_from(invalid).unwrap_err();
+        assert_eq!(error.utf8_error().valid_up_to(), 10);
+        assert_eq!(error.into_bytes().len(), 15);
+    }
+}
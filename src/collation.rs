@@ -0,0 +1,375 @@
+//! Collations, comparing strings after folding away differences in case or accents. Folding maps each char to at most
+//! one char, and folded strings compare by char, which is the same as comparing their utf-8 bytes.
+
+use std::{
+    cmp::Ordering,
+    hash::{Hash, Hasher},
+    ops::Deref,
+};
+
+use crate::{arc::UmbraArcString, common::UmbraLayout};
+
+mod case_folding;
+
+use case_folding::SIMPLE_CASE_FOLDING;
+
+/// How differences in case are folded away
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum CaseFolding {
+    /// Case is significant
+    #[default]
+    Sensitive,
+    /// Only ASCII letters are folded, other chars compare as they are
+    Ascii,
+    /// Unicode simple case folding, where every char folds to a single char
+    Simple,
+}
+
+/// A way of comparing strings, the default compares bytes like Ord
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct Collation {
+    pub case: CaseFolding,
+    /// Strips combining diacritical marks, and folds accented Latin-1 and Latin Extended-A letters to their base letter
+    pub ignore_accents: bool,
+}
+
+/// Base letters of U+00C0 to U+00FF, letters which aren't a base letter with an accent map to themselves
+const LATIN_1_BASE: &str = "AAAAAAÆCEEEEIIIIDNOOOOO×OUUUUYÞßaaaaaaæceeeeiiiidnooooo÷ouuuuyþy";
+
+/// Base letters of U+0100 to U+017F
+const LATIN_EXTENDED_A_BASE: &str = concat!(
+    "AaAaAaCcCcCcCcDdDdEeEeEeEeEeGgGgGgGgHhHhIiIiIiIiIiĲĳJjKkĸLlLlLlLlLlNnNnNnŉŊŋ",
+    "OoOoOoŒœRrRrRrSsSsSsSsTtTtTtUuUuUuUuUuUuWwYyYZzZzZzſ"
+);
+
+impl Collation {
+    pub const BINARY: Collation = Collation {
+        case: CaseFolding::Sensitive,
+        ignore_accents: false,
+    };
+
+    pub const ASCII_CASE_INSENSITIVE: Collation = Collation {
+        case: CaseFolding::Ascii,
+        ignore_accents: false,
+    };
+
+    pub const CASE_INSENSITIVE: Collation = Collation {
+        case: CaseFolding::Simple,
+        ignore_accents: false,
+    };
+
+    pub const ACCENT_INSENSITIVE: Collation = Collation {
+        case: CaseFolding::Sensitive,
+        ignore_accents: true,
+    };
+
+    fn is_binary(&self) -> bool {
+        *self == Self::BINARY
+    }
+
+    /// Whether a byte of a string folds to a single byte regardless of what is around it. The prefix fast path compares
+    /// folded bytes up to the first byte where this doesn't hold
+    fn folds_bytewise(&self, byte: u8) -> bool {
+        byte.is_ascii() || (self.case == CaseFolding::Ascii && !self.ignore_accents)
+    }
+
+    fn fold_byte(&self, byte: u8) -> u8 {
+        match self.case {
+            CaseFolding::Sensitive => byte,
+            CaseFolding::Ascii | CaseFolding::Simple => byte.to_ascii_lowercase(),
+        }
+    }
+
+    /// Folds a char, or gives None for a char which is ignored
+    pub fn fold_char(&self, c: char) -> Option<char> {
+        let c = if self.ignore_accents {
+            strip_accent(c)?
+        } else {
+            c
+        };
+        Some(match self.case {
+            CaseFolding::Sensitive => c,
+            CaseFolding::Ascii => c.to_ascii_lowercase(),
+            CaseFolding::Simple => simple_fold(c),
+        })
+    }
+
+    /// The chars of a string after folding
+    pub fn fold<'a>(&'a self, val: &'a str) -> impl Iterator<Item = char> + Clone + 'a {
+        val.chars().filter_map(|c| self.fold_char(c))
+    }
+}
+
+fn strip_accent(c: char) -> Option<char> {
+    match c as u32 {
+        // combining diacritical marks, as found in decomposed strings
+        0x300..=0x36F => None,
+        code @ 0xC0..=0xFF => LATIN_1_BASE.chars().nth((code - 0xC0) as usize),
+        code @ 0x100..=0x17F => LATIN_EXTENDED_A_BASE.chars().nth((code - 0x100) as usize),
+        _ => Some(c),
+    }
+}
+
+/// Simple case folding, the fold CaseFolding.txt gives each char with status C or S
+fn simple_fold(c: char) -> char {
+    if c.is_ascii() {
+        return c.to_ascii_lowercase();
+    }
+    match SIMPLE_CASE_FOLDING.binary_search_by_key(&c, |&(from, _)| from) {
+        Ok(i) => SIMPLE_CASE_FOLDING[i].1,
+        Err(_) => c,
+    }
+}
+
+impl UmbraArcString {
+    /// Compares under a collation, strings which only differ in what it folds away are equal
+    ///
+    /// Folded prefixes are compared first while they are ASCII, so most comparisons never look past the header.
+    pub fn cmp_with(&self, other: &UmbraArcString, collation: &Collation) -> Ordering {
+        if collation.is_binary() {
+            return self.cmp(other);
+        }
+
+        let (a_prefix, b_prefix) = (self.raw_prefix(), other.raw_prefix());
+        let prefix_len = self.len().min(other.len()).min(4);
+        for (&a, &b) in a_prefix[..prefix_len].iter().zip(&b_prefix[..prefix_len]) {
+            if !collation.folds_bytewise(a) || !collation.folds_bytewise(b) {
+                break;
+            }
+            match collation.fold_byte(a).cmp(&collation.fold_byte(b)) {
+                Ordering::Equal => {}
+                ordering => return ordering,
+            }
+        }
+
+        collation.fold(self).cmp(collation.fold(other))
+    }
+}
+
+/// Compares, hashes and orders the wrapped string ignoring case, for use as a key of case-insensitive identifiers
+///
+/// Equality, Hash and Ord all agree with cmp_with under Collation::CASE_INSENSITIVE.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct CaseInsensitive<T>(pub T);
+
+impl<T> CaseInsensitive<T> {
+    pub fn into_inner(self) -> T {
+        self.0
+    }
+}
+
+impl<T> Deref for CaseInsensitive<T> {
+    type Target = T;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+impl Eq for CaseInsensitive<UmbraArcString> {}
+
+impl PartialEq for CaseInsensitive<UmbraArcString> {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Hash for CaseInsensitive<UmbraArcString> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        let mut chunk = [0; 64];
+        let mut len = 0;
+        for c in Collation::CASE_INSENSITIVE.fold(&self.0) {
+            if len + c.len_utf8() > chunk.len() {
+                state.write(&chunk[..len]);
+                len = 0;
+            }
+            len += c.encode_utf8(&mut chunk[len..]).len();
+        }
+        state.write(&chunk[..len]);
+        // like str, so a string isn't hashed the same as a string it is a prefix of followed by something else
+        state.write_u8(0xff);
+    }
+}
+
+impl Ord for CaseInsensitive<UmbraArcString> {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.0.cmp_with(&other.0, &Collation::CASE_INSENSITIVE)
+    }
+}
+
+impl PartialOrd for CaseInsensitive<UmbraArcString> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        cmp::Ordering,
+        collections::HashMap,
+        hash::{BuildHasher, RandomState},
+    };
+
+    use super::{CaseInsensitive, Collation};
+    use crate::arc::UmbraArcString;
+
+    fn cmp(a: &str, b: &str, collation: Collation) -> Ordering {
+        UmbraArcString::new(a).cmp_with(&UmbraArcString::new(b), &collation)
+    }
+
+    #[test]
+    fn case_test() {
+        assert_eq!(cmp("Apple", "apricot", Collation::BINARY), Ordering::Less);
+        assert_eq!(
+            cmp("apple", "Apricot", Collation::BINARY),
+            Ordering::Greater
+        );
+        assert_eq!(
+            cmp("apple", "Apricot", Collation::ASCII_CASE_INSENSITIVE),
+            Ordering::Less
+        );
+        assert_eq!(
+            cmp(
+                "HELLO, a string on the heap",
+                "hello, A STRING ON THE HEAP",
+                Collation::ASCII_CASE_INSENSITIVE
+            ),
+            Ordering::Equal
+        );
+
+        assert_ne!(
+            cmp("ÉCOLE", "école", Collation::ASCII_CASE_INSENSITIVE),
+            Ordering::Equal
+        );
+        assert_eq!(
+            cmp("ÉCOLE", "école", Collation::CASE_INSENSITIVE),
+            Ordering::Equal
+        );
+        assert_eq!(
+            cmp("ΟΔΟΣ", "οδος", Collation::CASE_INSENSITIVE),
+            Ordering::Equal
+        );
+        assert_eq!(
+            cmp("ΟΔΟΣ", "οδoς", Collation::CASE_INSENSITIVE),
+            Ordering::Greater
+        );
+    }
+
+    #[test]
+    fn simple_folding_test() {
+        let collation = Collation::CASE_INSENSITIVE;
+        // micro sign, combining ypogegrammeni, prosgegrammeni and the narrow Cyrillic ve all fold to a Greek or Cyrillic
+        // letter whose uppercase is something else
+        assert_eq!(cmp("\u{b5}", "μ", collation), Ordering::Equal);
+        assert_eq!(cmp("\u{b5}", "Μ", collation), Ordering::Equal);
+        assert_eq!(cmp("\u{345}", "ι", collation), Ordering::Equal);
+        assert_eq!(cmp("\u{1fbe}", "ι", collation), Ordering::Equal);
+        assert_eq!(cmp("\u{1c80}", "в", collation), Ordering::Equal);
+        assert_eq!(cmp("\u{1c80}", "В", collation), Ordering::Equal);
+        assert_eq!(cmp("\u{212a}elvin", "kelvin", collation), Ordering::Equal);
+        assert_eq!(cmp("STRA\u{1e9e}E", "straße", collation), Ordering::Equal);
+
+        // dotted and dotless i only fold to themselves outside of Turkic languages
+        assert_ne!(cmp("\u{130}", "i", collation), Ordering::Equal);
+        assert_ne!(cmp("\u{131}", "i", collation), Ordering::Equal);
+    }
+
+    #[test]
+    fn accent_test() {
+        let collation = Collation::ACCENT_INSENSITIVE;
+        assert_eq!(cmp("résumé", "resume", collation), Ordering::Equal);
+        assert_eq!(
+            cmp("re\u{301}sume\u{301}", "résumé", collation),
+            Ordering::Equal
+        );
+        assert_eq!(cmp("Łódź", "Lodz", collation), Ordering::Equal);
+        assert_eq!(cmp("Résumé", "resume", collation), Ordering::Less);
+        assert_eq!(
+            cmp(
+                "Résumé",
+                "resume",
+                Collation {
+                    ignore_accents: true,
+                    ..Collation::CASE_INSENSITIVE
+                }
+            ),
+            Ordering::Equal
+        );
+    }
+
+    #[test]
+    fn prefix_test() {
+        let values = [
+            "",
+            "a",
+            "A",
+            "ab",
+            "aB",
+            "abcd",
+            "ABCDE",
+            "abcdefghijklmnop",
+            "b",
+            "é",
+            "É",
+            "ÉA",
+            "z",
+        ];
+        for collation in [
+            Collation::ASCII_CASE_INSENSITIVE,
+            Collation::CASE_INSENSITIVE,
+            Collation::ACCENT_INSENSITIVE,
+        ] {
+            for a in values {
+                for b in values {
+                    let expected = collation.fold(a).cmp(collation.fold(b));
+                    assert_eq!(cmp(a, b, collation), expected, "{a:?} {b:?} {collation:?}");
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn case_insensitive_test() {
+        let key = |val: &str| CaseInsensitive(UmbraArcString::new(val));
+        let mut tables = HashMap::new();
+        tables.insert(key("Customers"), 1);
+        tables.insert(key("an_identifier_long_enough_for_the_heap"), 2);
+
+        assert_eq!(tables.get(&key("CUSTOMERS")), Some(&1));
+        assert_eq!(
+            tables.get(&key("An_Identifier_Long_Enough_For_The_Heap")),
+            Some(&2)
+        );
+        assert_eq!(tables.get(&key("customer")), None);
+
+        tables.insert(key("\u{b5}s"), 3);
+        assert_eq!(tables.get(&key("ΜS")), Some(&3));
+        assert_eq!(key("\u{212a}elvin"), key("KELVIN"));
+
+        let hasher = RandomState::new();
+        let values = [
+            "",
+            "abc",
+            "ABC",
+            "abd",
+            "Straße",
+            "STRAßE",
+            "STRA\u{1e9e}E",
+            "\u{212a}elvin",
+            "kelvin",
+            "\u{1fbe}",
+            "ι",
+            "Ι",
+            "a string which is too long to inline",
+        ];
+        for a in values.map(key) {
+            for b in values.map(key) {
+                assert_eq!(a == b, a.cmp(&b) == Ordering::Equal, "{a:?} {b:?}");
+                if a == b {
+                    assert_eq!(hasher.hash_one(&a), hasher.hash_one(&b));
+                }
+            }
+        }
+    }
+}
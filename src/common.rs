@@ -0,0 +1,262 @@
+//! Pieces shared by the Umbra string types. They all use the same 16 byte `#[repr(C)]` layout: a u32 length,
+//! a 4 byte prefix, then 8 bytes holding either the rest of an inline string or a pointer to the heap.
+
+use std::{
+    alloc::{self, Layout},
+    cmp::Ordering,
+    fmt,
+    mem::transmute,
+    ops::{Deref, DerefMut},
+    ptr,
+};
+
+use crate::{arc::MAX_INLINE, UmbraError};
+
+/// Size of the two word header at the start of every out-of-line allocation, the bytes follow directly after it. Every
+/// type uses the same size so allocations can be handed between them without moving the bytes
+#[cfg(all(not(loom), target_pointer_width = "64"))]
+pub(crate) const HEADER_SIZE: usize = size_of::<[usize; 2]>();
+/// Under `--cfg loom` its atomics are each a word, as are all its fields on 32 bit targets, so UmbraArcString's header
+/// takes three
+#[cfg(any(loom, not(target_pointer_width = "64")))]
+pub(crate) const HEADER_SIZE: usize = size_of::<[usize; 3]>();
+
+/// Words of the header after the first, which only UmbraArcString uses
+pub(crate) const RESERVED_WORDS: usize = HEADER_SIZE / size_of::<usize>() - 1;
+
+/// Largest capacity of the shared data buffers columns append long strings to, a longer string gets a buffer of its own
+pub(crate) const BUFFER_SIZE: usize = 2 * 1024 * 1024;
+
+/// Capacity of a column's first data buffer, each one after it doubles until BUFFER_SIZE so small columns stay small
+const FIRST_BUFFER_SIZE: usize = 8 * 1024;
+
+/// Capacity of the next data buffer of a column which has `buffers` already, with room for at least len bytes
+pub(crate) fn next_buffer_size(buffers: usize, len: usize) -> usize {
+    let doublings = (BUFFER_SIZE / FIRST_BUFFER_SIZE).ilog2() as usize;
+    (FIRST_BUFFER_SIZE << buffers.min(doublings)).max(len)
+}
+
+/// Checks that a length fits in the u32 length field
+pub(crate) fn checked_len(len: usize) -> Result<u32, UmbraError> {
+    u32::try_from(len).map_err(|_| UmbraError::LengthOverflow { len })
+}
+
+/// Turns an error from a fallible constructor into the panic or abort of the infallible one
+pub(crate) fn unwrap_new<T>(result: Result<T, UmbraError>) -> T {
+    match result {
+        Ok(val) => val,
+        Err(UmbraError::AllocError { layout }) => alloc::handle_alloc_error(layout),
+        Err(err) => panic!("{err}"),
+    }
+}
+
+/// Splits a string of at most MAX_INLINE bytes into its zero padded prefix and inline data
+pub(crate) fn split_inline(bytes: &[u8]) -> ([u8; 4], [u8; 8]) {
+    let mut inline: [u8; 12] = [0; 12];
+    inline[..bytes.len()].copy_from_slice(bytes);
+    // SAFETY: inline is of length 12 and align 1, and it is being split into arrays of length 4 and 8
+    unsafe { transmute(inline) }
+}
+
+/// The prefix of a string which is too long to be inlined
+pub(crate) fn heap_prefix(bytes: &[u8]) -> [u8; 4] {
+    let mut prefix = [0; 4];
+    prefix.copy_from_slice(&bytes[0..4]);
+    prefix
+}
+
+/// SAFETY: T must be #[repr(C)] with the Umbra layout, and its inline data must be active
+pub(crate) unsafe fn inline_bytes<T>(val: &T, len: u32) -> &[u8] {
+    debug_assert!(len as usize <= MAX_INLINE);
+    // SAFETY: the 12 bytes after the length are the prefix followed by the data, pointer is derived from val so covers both
+    let byte_arr = unsafe { &*ptr::from_ref(val).cast::<u8>().add(4).cast::<[u8; 12]>() };
+    &byte_arr[..len as usize]
+}
+
+/// SAFETY: T must be #[repr(C)] with the Umbra layout, and its inline data must be active
+pub(crate) unsafe fn inline_bytes_mut<T>(val: &mut T, len: u32) -> &mut [u8] {
+    debug_assert!(len as usize <= MAX_INLINE);
+    // SAFETY: as in inline_bytes, and val is borrowed mutably
+    let byte_arr = unsafe { &mut *ptr::from_mut(val).cast::<u8>().add(4).cast::<[u8; 12]>() };
+    &mut byte_arr[..len as usize]
+}
+
+/// Layout of an out-of-line allocation holding a header and len bytes
+pub(crate) fn heap_layout(len: usize) -> Layout {
+    Layout::from_size_align(HEADER_SIZE, align_of::<usize>())
+        .expect("header layout")
+        .extend(Layout::array::<u8>(len).expect("string too large"))
+        .expect("string too large")
+        .0
+}
+
+/// Allocates room for a header and len bytes, the header is left uninitialised
+pub(crate) fn heap_alloc(len: usize) -> *mut u8 {
+    unwrap_new(try_heap_alloc(len))
+}
+
+/// Allocates room for a header and len bytes, the header is left uninitialised
+pub(crate) fn try_heap_alloc(len: usize) -> Result<*mut u8, UmbraError> {
+    let layout = heap_layout(len);
+
+    // SAFETY: layout always has a non-zero size as it contains the header
+    let ptr = unsafe { alloc::alloc(layout) };
+    if ptr.is_null() {
+        return Err(UmbraError::AllocError { layout });
+    }
+    Ok(ptr)
+}
+
+/// Access to the parts of an Umbra string needed to compare it, without going through Deref
+pub(crate) trait UmbraLayout {
+    fn raw_len(&self) -> u32;
+
+    fn raw_prefix(&self) -> [u8; 4];
+
+    /// The 8 bytes after the prefix, if the string is inline
+    fn inline_data(&self) -> Option<&[u8; 8]>;
+
+    /// The bytes of the string after the prefix
+    fn suffix_bytes(&self) -> &[u8];
+}
+
+#[inline]
+pub(crate) fn umbra_eq<A: UmbraLayout + ?Sized, B: UmbraLayout + ?Sized>(a: &A, b: &B) -> bool {
+    if a.raw_len() != b.raw_len() || a.raw_prefix() != b.raw_prefix() {
+        return false;
+    }
+
+    match (a.inline_data(), b.inline_data()) {
+        (Some(a_data), Some(b_data)) => a_data == b_data,
+        _ => {
+            let (a_suffix, b_suffix) = (a.suffix_bytes(), b.suffix_bytes());
+            // strings sharing an allocation, such as clones or interned strings, are equal without reading the bytes
+            ptr::eq(a_suffix.as_ptr(), b_suffix.as_ptr()) || a_suffix == b_suffix
+        }
+    }
+}
+
+#[inline]
+pub(crate) fn umbra_cmp<A: UmbraLayout + ?Sized, B: UmbraLayout + ?Sized>(
+    a: &A,
+    b: &B,
+) -> Ordering {
+    match a.raw_prefix().cmp(&b.raw_prefix()) {
+        Ordering::Equal => {
+            if a.raw_len() <= 4 && b.raw_len() <= 4 {
+                a.raw_len().cmp(&b.raw_len())
+            } else if let (Some(a_data), Some(b_data)) = (a.inline_data(), b.inline_data()) {
+                a_data
+                    .cmp(b_data)
+                    .then_with(|| a.raw_len().cmp(&b.raw_len()))
+            } else {
+                a.suffix_bytes().cmp(b.suffix_bytes())
+            }
+        }
+        ordering => ordering,
+    }
+}
+
+/// Mutable access to the contents of an Umbra string, the cached prefix is brought back in sync when this is dropped
+pub struct UmbraStrMut<'a> {
+    /// The prefix of a string which is not inline, inline strings use the prefix as part of their contents
+    prefix: Option<&'a mut [u8; 4]>,
+    contents: &'a mut str,
+}
+
+impl<'a> UmbraStrMut<'a> {
+    pub(crate) fn new(prefix: Option<&'a mut [u8; 4]>, contents: &'a mut str) -> Self {
+        Self { prefix, contents }
+    }
+}
+
+impl Deref for UmbraStrMut<'_> {
+    type Target = str;
+
+    fn deref(&self) -> &Self::Target {
+        self.contents
+    }
+}
+
+impl DerefMut for UmbraStrMut<'_> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        self.contents
+    }
+}
+
+impl fmt::Debug for UmbraStrMut<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(&**self, f)
+    }
+}
+
+impl Drop for UmbraStrMut<'_> {
+    fn drop(&mut self) {
+        if let Some(prefix) = &mut self.prefix {
+            prefix.copy_from_slice(&self.contents.as_bytes()[..4]);
+        }
+    }
+}
+
+/// Implements the traits which behave the same for every Umbra string type, in terms of Deref<Target = str> and UmbraLayout
+macro_rules! impl_umbra_str_traits {
+    ($ty:ty) => {
+        impl AsRef<str> for $ty {
+            fn as_ref(&self) -> &str {
+                self
+            }
+        }
+
+        impl ::std::fmt::Display for $ty {
+            fn fmt(&self, f: &mut ::std::fmt::Formatter<'_>) -> ::std::fmt::Result {
+                ::std::fmt::Display::fmt(&**self, f)
+            }
+        }
+
+        impl ::std::fmt::Debug for $ty {
+            fn fmt(&self, f: &mut ::std::fmt::Formatter<'_>) -> ::std::fmt::Result {
+                ::std::fmt::Debug::fmt(&**self, f)
+            }
+        }
+
+        impl ::std::hash::Hash for $ty {
+            fn hash<H: ::std::hash::Hasher>(&self, state: &mut H) {
+                (**self).hash(state)
+            }
+        }
+
+        impl Eq for $ty {}
+
+        impl PartialEq<$ty> for $ty {
+            fn eq(&self, other: &$ty) -> bool {
+                $crate::common::umbra_eq(self, other)
+            }
+        }
+
+        impl PartialEq<&str> for $ty {
+            fn eq(&self, other: &&str) -> bool {
+                self.as_ref() == *other
+            }
+        }
+
+        impl Ord for $ty {
+            fn cmp(&self, other: &Self) -> ::std::cmp::Ordering {
+                $crate::common::umbra_cmp(self, other)
+            }
+        }
+
+        impl PartialOrd<$ty> for $ty {
+            fn partial_cmp(&self, other: &$ty) -> Option<::std::cmp::Ordering> {
+                Some(self.cmp(other))
+            }
+        }
+
+        impl PartialOrd<&str> for $ty {
+            fn partial_cmp(&self, other: &&str) -> Option<::std::cmp::Ordering> {
+                Some(self.as_ref().cmp(other))
+            }
+        }
+    };
+}
+
+pub(crate) use impl_umbra_str_traits;
@@ -0,0 +1,237 @@
+//! Dictionary encoding, where a column stores a u32 code per row and each distinct string once
+
+use std::collections::HashMap;
+
+use crate::arc::UmbraArcString;
+
+/// A column of strings stored as codes into a dictionary of the distinct values
+#[derive(Debug, Clone)]
+pub struct DictionaryColumn {
+    /// The distinct strings, indexed by code
+    values: Vec<UmbraArcString>,
+    lookup: HashMap<UmbraArcString, u32>,
+    codes: Vec<u32>,
+    /// Whether values is in ascending order, so codes compare the same way as the strings
+    sorted: bool,
+}
+
+impl Default for DictionaryColumn {
+    fn default() -> Self {
+        Self {
+            values: Vec::new(),
+            lookup: HashMap::new(),
+            codes: Vec::new(),
+            sorted: true,
+        }
+    }
+}
+
+impl DictionaryColumn {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Encodes a column, the dictionary holds clones sharing the allocations of the first occurrence of each string
+    pub fn encode(values: &[UmbraArcString]) -> Self {
+        values.iter().collect()
+    }
+
+    /// Encodes a column with a sorted dictionary, so codes compare in the same order as the strings
+    pub fn encode_sorted(values: &[UmbraArcString]) -> Self {
+        let mut column = Self::encode(values);
+        column.sort_dictionary();
+        column
+    }
+
+    /// The number of rows
+    pub fn len(&self) -> usize {
+        self.codes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.codes.is_empty()
+    }
+
+    /// The number of distinct strings
+    pub fn cardinality(&self) -> usize {
+        self.values.len()
+    }
+
+    /// Appends a row, returning its code
+    ///
+    /// Panics if the dictionary already has u32::MAX + 1 strings
+    pub fn push(&mut self, val: &UmbraArcString) -> u32 {
+        let code = self.code_or_insert(val);
+        self.codes.push(code);
+        code
+    }
+
+    fn code_or_insert(&mut self, val: &UmbraArcString) -> u32 {
+        if let Some(&code) = self.lookup.get(val) {
+            return code;
+        }
+
+        let code = u32::try_from(self.values.len()).expect("dictionary has too many strings");
+        if self.values.last().is_some_and(|last| last > val) {
+            self.sorted = false;
+        }
+        self.values.push(val.clone());
+        self.lookup.insert(val.clone(), code);
+        code
+    }
+
+    /// The code of a string, if it is in the dictionary
+    pub fn code(&self, val: &str) -> Option<u32> {
+        self.lookup.get(val).copied()
+    }
+
+    /// Panics if code isn't in the dictionary
+    pub fn value(&self, code: u32) -> &UmbraArcString {
+        &self.values[code as usize]
+    }
+
+    /// The string of a row
+    ///
+    /// Panics if row is out of bounds
+    pub fn get(&self, row: usize) -> &UmbraArcString {
+        self.value(self.codes[row])
+    }
+
+    pub fn codes(&self) -> &[u32] {
+        &self.codes
+    }
+
+    pub fn dictionary(&self) -> &[UmbraArcString] {
+        &self.values
+    }
+
+    pub fn is_sorted(&self) -> bool {
+        self.sorted
+    }
+
+    /// Decodes every row, the strings are clones of the dictionary entries so long ones aren't copied
+    pub fn decode(&self) -> Vec<UmbraArcString> {
+        self.codes
+            .iter()
+            .map(|&code| self.value(code).clone())
+            .collect()
+    }
+
+    /// Sorts the dictionary and rewrites the codes to match, so codes compare in the same order as the strings
+    pub fn sort_dictionary(&mut self) {
+        if self.sorted {
+            return;
+        }
+
+        let mut order: Vec<u32> = (0..self.values.len() as u32).collect();
+        order.sort_unstable_by(|&a, &b| self.values[a as usize].cmp(&self.values[b as usize]));
+
+        let mut remap = vec![0; order.len()];
+        for (new_code, &old_code) in order.iter().enumerate() {
+            remap[old_code as usize] = new_code as u32;
+        }
+        self.remap(&remap);
+        self.sorted = true;
+    }
+
+    /// Appends the rows of another column, returning the code in this dictionary of each of its codes
+    ///
+    /// Panics if the merged dictionary would have more than u32::MAX + 1 strings
+    pub fn merge(&mut self, other: &DictionaryColumn) -> Vec<u32> {
+        let remap: Vec<u32> = other
+            .values
+            .iter()
+            .map(|val| self.code_or_insert(val))
+            .collect();
+        self.codes
+            .extend(other.codes.iter().map(|&code| remap[code as usize]));
+        remap
+    }
+
+    /// Moves every string from its code to remap[code], remap must be a permutation of the codes
+    fn remap(&mut self, remap: &[u32]) {
+        let mut values = vec![None; self.values.len()];
+        for (old_code, val) in self.values.drain(..).enumerate() {
+            values[remap[old_code] as usize] = Some(val);
+        }
+        self.values = values
+            .into_iter()
+            .map(|val| val.expect("remap is a permutation"))
+            .collect();
+
+        for code in self.lookup.values_mut() {
+            *code = remap[*code as usize];
+        }
+        for code in &mut self.codes {
+            *code = remap[*code as usize];
+        }
+    }
+}
+
+impl<'a> FromIterator<&'a UmbraArcString> for DictionaryColumn {
+    fn from_iter<T: IntoIterator<Item = &'a UmbraArcString>>(iter: T) -> Self {
+        let mut column = DictionaryColumn::new();
+        column.extend(iter);
+        column
+    }
+}
+
+impl<'a> Extend<&'a UmbraArcString> for DictionaryColumn {
+    fn extend<T: IntoIterator<Item = &'a UmbraArcString>>(&mut self, iter: T) {
+        for val in iter {
+            self.push(val);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::DictionaryColumn;
+    use crate::arc::UmbraArcString;
+
+    fn strings(vals: &[&str]) -> Vec<UmbraArcString> {
+        vals.iter().map(UmbraArcString::new).collect()
+    }
+
+    #[test]
+    fn encode_decode_test() {
+        let rows = strings(&["pending", "a status too long to inline", "pending", "done"]);
+        let column = DictionaryColumn::encode(&rows);
+
+        assert_eq!(column.cardinality(), 3);
+        assert_eq!(column.codes(), [0, 1, 0, 2]);
+        assert_eq!(column.code("done"), Some(2));
+        assert_eq!(column.decode(), rows);
+        assert_eq!(column.get(1).as_ptr(), rows[1].as_ptr());
+    }
+
+    #[test]
+    fn merge_test() {
+        let mut column = DictionaryColumn::encode(&strings(&["fr", "de", "fr"]));
+        let other = DictionaryColumn::encode(&strings(&["us", "fr", "us"]));
+
+        assert_eq!(column.merge(&other), [2, 0]);
+        assert_eq!(column.codes(), [0, 1, 0, 2, 0, 2]);
+        assert_eq!(
+            column.decode(),
+            strings(&["fr", "de", "fr", "us", "fr", "us"])
+        );
+    }
+
+    #[test]
+    fn sorted_test() {
+        let rows = strings(&["uk", "a long country name to go on the heap", "de", "uk"]);
+        let mut column = DictionaryColumn::encode_sorted(&rows);
+
+        assert!(column.is_sorted());
+        assert_eq!(column.decode(), rows);
+        for (a, b) in column.codes().iter().zip(&column.codes()[1..]) {
+            assert_eq!(a.cmp(b), column.value(*a).cmp(column.value(*b)));
+        }
+
+        column.push(&UmbraArcString::new("zz"));
+        assert!(column.is_sorted());
+        column.push(&UmbraArcString::new("aa"));
+        assert!(!column.is_sorted());
+    }
+}
@@ -0,0 +1,37 @@
+use std::{alloc::Layout, fmt};
+
+/// The ways in which building an Umbra string can fail
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum UmbraError {
+    /// The string is longer than the u32::MAX bytes which can be stored in the length field
+    LengthOverflow { len: usize },
+    /// The allocator couldn't provide memory for the out-of-line bytes
+    AllocError { layout: Layout },
+    /// A view imported from outside the crate doesn't describe a valid string
+    InvalidView { index: usize },
+    /// An array imported through the Arrow C Data Interface isn't a utf8_view array this crate can read
+    InvalidArray { reason: &'static str },
+    /// A sort key being decoded is truncated or wasn't written with the same options
+    InvalidSortKey,
+    /// A LIKE or GLOB pattern is malformed, such as ending in an escape character
+    InvalidPattern { reason: &'static str },
+}
+
+impl fmt::Display for UmbraError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            UmbraError::LengthOverflow { len } => {
+                write!(f, "string of {len} bytes is longer than u32::MAX")
+            }
+            UmbraError::AllocError { layout } => {
+                write!(f, "failed to allocate {} bytes", layout.size())
+            }
+            UmbraError::InvalidView { index } => write!(f, "view {index} is not a valid string"),
+            UmbraError::InvalidArray { reason } => write!(f, "invalid arrow array: {reason}"),
+            UmbraError::InvalidSortKey => write!(f, "invalid sort key"),
+            UmbraError::InvalidPattern { reason } => write!(f, "invalid pattern: {reason}"),
+        }
+    }
+}
+
+impl std::error::Error for UmbraError {}
@@ -0,0 +1,597 @@
+//! FSST (Fast Static Symbol Table) compression of string columns. A symbol table of up to 255 symbols of 1 to 8 bytes
+//! is trained on a sample, then each string is encoded as one byte codes standing for symbols, with an escape code for
+//! bytes no symbol covers. Strings are compressed on their own so any one of them can be decompressed without the
+//! others, and the header of each keeps its length and uncompressed 4 byte prefix like an Umbra string.
+
+use std::{
+    cmp::Ordering,
+    collections::HashMap,
+    fmt::{self, Debug},
+    iter, ptr,
+    sync::Arc,
+};
+
+use crate::{
+    arc::{UmbraArcString, MAX_INLINE},
+    common::UmbraLayout,
+};
+
+/// Code which is followed by a byte to copy as it is
+const ESCAPE: u8 = 255;
+
+const MAX_SYMBOLS: usize = 255;
+
+const MAX_SYMBOL_LEN: usize = 8;
+
+/// Rounds of counting symbols and combining the most useful pairs of them
+const GENERATIONS: usize = 5;
+
+/// Training only looks at about this many bytes of the sample, more barely changes the table
+const MAX_SAMPLE_BYTES: usize = 1 << 16;
+
+/// A trained table of symbols, codes are indexes into it
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SymbolTable {
+    symbols: Vec<Vec<u8>>,
+    /// Codes of the symbols starting with each byte, longest first so encoding picks the longest match
+    by_first: Vec<Vec<u8>>,
+}
+
+impl SymbolTable {
+    fn from_symbols(symbols: Vec<Vec<u8>>) -> Self {
+        debug_assert!(symbols.len() <= MAX_SYMBOLS);
+        let mut by_first = vec![Vec::new(); 256];
+        for (code, symbol) in symbols.iter().enumerate() {
+            by_first[symbol[0] as usize].push(code as u8);
+        }
+        for codes in &mut by_first {
+            codes.sort_by_key(|&code| std::cmp::Reverse(symbols[code as usize].len()));
+        }
+        Self { symbols, by_first }
+    }
+
+    /// Trains a table on the parts of the strings after their prefixes, which are what gets compressed
+    pub fn train(sample: &[UmbraArcString]) -> Self {
+        let mut suffixes = Vec::new();
+        let mut sample_bytes = 0;
+        for val in sample {
+            if sample_bytes >= MAX_SAMPLE_BYTES {
+                break;
+            }
+            suffixes.push(val.suffix_bytes());
+            sample_bytes += val.suffix_bytes().len();
+        }
+
+        let mut table = SymbolTable::default();
+        for _ in 0..GENERATIONS {
+            let mut counts: HashMap<&[u8], usize> = HashMap::new();
+            for suffix in &suffixes {
+                let mut pos = 0;
+                let mut prev: Option<&[u8]> = None;
+                while pos < suffix.len() {
+                    let len = table
+                        .longest_match(&suffix[pos..])
+                        .map_or(1, |code| table.symbols[code as usize].len());
+                    // single bytes are always candidates, so an escaped byte can become a symbol
+                    *counts.entry(&suffix[pos..pos + 1]).or_default() += 1;
+                    if len > 1 {
+                        *counts.entry(&suffix[pos..pos + len]).or_default() += 1;
+                    }
+                    if let Some(prev) = prev.filter(|prev| prev.len() + len <= MAX_SYMBOL_LEN) {
+                        let start = pos - prev.len();
+                        *counts.entry(&suffix[start..pos + len]).or_default() += 1;
+                    }
+                    prev = Some(&suffix[pos..pos + len]);
+                    pos += len;
+                }
+            }
+
+            // a symbol saves a byte per byte it covers each time it is used
+            let mut candidates: Vec<(&[u8], usize)> = counts.into_iter().collect();
+            candidates.sort_unstable_by(|(a_symbol, a_count), (b_symbol, b_count)| {
+                (b_count * b_symbol.len())
+                    .cmp(&(a_count * a_symbol.len()))
+                    .then_with(|| a_symbol.cmp(b_symbol))
+            });
+            candidates.truncate(MAX_SYMBOLS);
+            table = Self::from_symbols(
+                candidates
+                    .into_iter()
+                    .map(|(symbol, _)| symbol.to_vec())
+                    .collect(),
+            );
+        }
+        table
+    }
+
+    pub fn len(&self) -> usize {
+        self.symbols.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.symbols.is_empty()
+    }
+
+    fn longest_match(&self, bytes: &[u8]) -> Option<u8> {
+        self.by_first
+            .get(*bytes.first()? as usize)?
+            .iter()
+            .copied()
+            .find(|&code| bytes.starts_with(&self.symbols[code as usize]))
+    }
+
+    /// Compresses bytes, appending the codes to out
+    pub fn compress(&self, mut bytes: &[u8], out: &mut Vec<u8>) {
+        while let Some(&byte) = bytes.first() {
+            match self.longest_match(bytes) {
+                Some(code) => {
+                    out.push(code);
+                    bytes = &bytes[self.symbols[code as usize].len()..];
+                }
+                None => {
+                    out.extend_from_slice(&[ESCAPE, byte]);
+                    bytes = &bytes[1..];
+                }
+            }
+        }
+    }
+
+    /// Decompresses codes made by compress with this table, appending the bytes to out
+    ///
+    /// Panics if the codes weren't made with this table
+    pub fn decompress(&self, codes: &[u8], out: &mut Vec<u8>) {
+        let mut codes = codes.iter();
+        while let Some(&code) = codes.next() {
+            if code == ESCAPE {
+                out.push(*codes.next().expect("escape code at end of input"));
+            } else {
+                out.extend_from_slice(&self.symbols[code as usize]);
+            }
+        }
+    }
+}
+
+/// Header of a string in an FsstColumn
+#[derive(Debug, Clone, Copy)]
+struct FsstHeader {
+    len: u32,
+    prefix: [u8; 4],
+    /// The rest of an inline string, or the offset and length of the compressed bytes after the prefix
+    extra: [u8; 8],
+}
+
+impl FsstHeader {
+    fn is_inline(&self) -> bool {
+        self.len as usize <= MAX_INLINE
+    }
+
+    fn compressed_range(&self) -> (usize, usize) {
+        debug_assert!(!self.is_inline());
+        let offset = u32::from_le_bytes(self.extra[..4].try_into().unwrap()) as usize;
+        let len = u32::from_le_bytes(self.extra[4..].try_into().unwrap()) as usize;
+        (offset, offset + len)
+    }
+}
+
+/// A column of FSST compressed strings sharing one symbol table. Columns made from the same Arc of a table can compare
+/// their strings by their codes
+#[derive(Debug, Clone, Default)]
+pub struct FsstColumn {
+    table: Arc<SymbolTable>,
+    headers: Vec<FsstHeader>,
+    /// The compressed bytes of every string which isn't inline
+    data: Vec<u8>,
+}
+
+impl FsstColumn {
+    pub fn new(table: impl Into<Arc<SymbolTable>>) -> Self {
+        Self {
+            table: table.into(),
+            headers: Vec::new(),
+            data: Vec::new(),
+        }
+    }
+
+    /// Trains a table on the strings then compresses them with it
+    pub fn compress(values: &[UmbraArcString]) -> Self {
+        let mut column = Self::new(SymbolTable::train(values));
+        for val in values {
+            column.push(val);
+        }
+        column
+    }
+
+    /// Panics if the compressed bytes of the column grow past u32::MAX
+    pub fn push(&mut self, val: &UmbraArcString) {
+        let extra = match val.inline_data() {
+            Some(data) => *data,
+            None => {
+                let start = self.data.len();
+                self.table.compress(val.suffix_bytes(), &mut self.data);
+                let (Ok(offset), Ok(end)) = (u32::try_from(start), u32::try_from(self.data.len()))
+                else {
+                    panic!("compressed column longer than u32::MAX");
+                };
+                let mut extra = [0; 8];
+                extra[..4].copy_from_slice(&offset.to_le_bytes());
+                extra[4..].copy_from_slice(&(end - offset).to_le_bytes());
+                extra
+            }
+        };
+        self.headers.push(FsstHeader {
+            len: val.raw_len(),
+            prefix: val.raw_prefix(),
+            extra,
+        });
+    }
+
+    pub fn len(&self) -> usize {
+        self.headers.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.headers.is_empty()
+    }
+
+    pub fn table(&self) -> &Arc<SymbolTable> {
+        &self.table
+    }
+
+    /// The size of the compressed bytes, not counting the headers
+    pub fn compressed_size(&self) -> usize {
+        self.data.len()
+    }
+
+    pub fn get(&self, index: usize) -> Option<FsstStr<'_>> {
+        Some(FsstStr {
+            column: self,
+            header: self.headers.get(index)?,
+        })
+    }
+
+    pub fn iter(&self) -> impl ExactSizeIterator<Item = FsstStr<'_>> + '_ {
+        self.headers.iter().map(|header| FsstStr {
+            column: self,
+            header,
+        })
+    }
+}
+
+/// A compressed string in an FsstColumn, comparisons look at the length and prefix before decompressing anything
+#[derive(Clone, Copy)]
+pub struct FsstStr<'a> {
+    column: &'a FsstColumn,
+    header: &'a FsstHeader,
+}
+
+impl<'a> FsstStr<'a> {
+    pub fn len(&self) -> usize {
+        self.header.len as usize
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.header.len == 0
+    }
+
+    fn compressed_suffix(&self) -> &[u8] {
+        let (start, end) = self.header.compressed_range();
+        &self.column.data[start..end]
+    }
+
+    fn inline_suffix(&self) -> &'a [u8] {
+        debug_assert!(self.header.is_inline());
+        &self.header.extra[..self.len().saturating_sub(4)]
+    }
+
+    /// The bytes after the prefix, decompressed a symbol at a time as they are needed
+    fn suffix_chunks(&self) -> SuffixChunks<'a> {
+        if self.header.is_inline() {
+            return SuffixChunks {
+                table: &self.column.table,
+                inline: Some(self.inline_suffix()),
+                codes: &[],
+            };
+        }
+        let (start, end) = self.header.compressed_range();
+        SuffixChunks {
+            table: &self.column.table,
+            inline: None,
+            codes: &self.column.data[start..end],
+        }
+    }
+
+    /// Compares the bytes after the prefixes without allocating
+    fn cmp_suffix(&self, other: &Self) -> Ordering {
+        if self.header.is_inline() && other.header.is_inline() {
+            return self.inline_suffix().cmp(other.inline_suffix());
+        }
+        cmp_chunks(self.suffix_chunks(), other.suffix_chunks())
+    }
+
+    /// Decompresses the string, appending it to out
+    pub fn decompress_into(&self, out: &mut Vec<u8>) {
+        out.reserve(self.len());
+        out.extend_from_slice(&self.header.prefix[..self.len().min(4)]);
+        for chunk in self.suffix_chunks() {
+            out.extend_from_slice(chunk);
+        }
+    }
+
+    /// Decompresses the string, this only allocates if the string isn't inline
+    pub fn to_umbra(&self) -> UmbraArcString {
+        if self.header.is_inline() {
+            let mut bytes = [0; 12];
+            bytes[..4].copy_from_slice(&self.header.prefix);
+            bytes[4..].copy_from_slice(&self.header.extra);
+            // SAFETY: inline strings are stored as they are and were copied from an UmbraArcString, so are utf-8
+            return UmbraArcString::new(unsafe {
+                std::str::from_utf8_unchecked(&bytes[..self.len()])
+            });
+        }
+
+        let mut bytes = Vec::with_capacity(self.len());
+        self.decompress_into(&mut bytes);
+        // SAFETY: decompressing gives back the bytes of the UmbraArcString which was pushed
+        UmbraArcString::new(unsafe { String::from_utf8_unchecked(bytes) })
+    }
+}
+
+impl Debug for FsstStr<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        Debug::fmt(&self.to_umbra(), f)
+    }
+}
+
+impl Eq for FsstStr<'_> {}
+
+impl PartialEq for FsstStr<'_> {
+    fn eq(&self, other: &Self) -> bool {
+        if self.header.len != other.header.len || self.header.prefix != other.header.prefix {
+            return false;
+        }
+        if self.header.is_inline() {
+            return self.header.extra == other.header.extra;
+        }
+        // encoding is deterministic, so strings compressed with the same table are equal exactly when their codes are
+        if ptr::eq(self.column, other.column)
+            || Arc::ptr_eq(&self.column.table, &other.column.table)
+        {
+            return self.compressed_suffix() == other.compressed_suffix();
+        }
+        self.cmp_suffix(other) == Ordering::Equal
+    }
+}
+
+impl PartialEq<UmbraArcString> for FsstStr<'_> {
+    fn eq(&self, other: &UmbraArcString) -> bool {
+        self.header.len == other.raw_len()
+            && self.header.prefix == other.raw_prefix()
+            && cmp_chunks(self.suffix_chunks(), iter::once(other.suffix_bytes())) == Ordering::Equal
+    }
+}
+
+impl Ord for FsstStr<'_> {
+    fn cmp(&self, other: &Self) -> Ordering {
+        match self.header.prefix.cmp(&other.header.prefix) {
+            Ordering::Equal if self.header.len <= 4 && other.header.len <= 4 => {
+                self.header.len.cmp(&other.header.len)
+            }
+            Ordering::Equal => self.cmp_suffix(other),
+            ordering => ordering,
+        }
+    }
+}
+
+impl PartialOrd for FsstStr<'_> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialOrd<UmbraArcString> for FsstStr<'_> {
+    fn partial_cmp(&self, other: &UmbraArcString) -> Option<Ordering> {
+        Some(match self.header.prefix.cmp(&other.raw_prefix()) {
+            Ordering::Equal if self.header.len <= 4 && other.raw_len() <= 4 => {
+                self.header.len.cmp(&other.raw_len())
+            }
+            Ordering::Equal => cmp_chunks(self.suffix_chunks(), iter::once(other.suffix_bytes())),
+            ordering => ordering,
+        })
+    }
+}
+
+/// The decompressed bytes of a string after its prefix, each item is the rest of an inline string, a symbol, or an
+/// escaped byte
+struct SuffixChunks<'a> {
+    table: &'a SymbolTable,
+    inline: Option<&'a [u8]>,
+    codes: &'a [u8],
+}
+
+impl<'a> Iterator for SuffixChunks<'a> {
+    type Item = &'a [u8];
+
+    fn next(&mut self) -> Option<&'a [u8]> {
+        if let Some(inline) = self.inline.take() {
+            return Some(inline);
+        }
+        let (&code, rest) = self.codes.split_first()?;
+        if code == ESCAPE {
+            assert!(!rest.is_empty(), "escape code at end of input");
+            let (byte, rest) = rest.split_at(1);
+            self.codes = rest;
+            return Some(byte);
+        }
+        self.codes = rest;
+        Some(&self.table.symbols[code as usize])
+    }
+}
+
+/// Compares the concatenations of two sequences of chunks, stopping at the first difference
+fn cmp_chunks<'a, 'b>(
+    mut a: impl Iterator<Item = &'a [u8]>,
+    mut b: impl Iterator<Item = &'b [u8]>,
+) -> Ordering {
+    let (mut a_chunk, mut b_chunk): (&[u8], &[u8]) = (&[], &[]);
+    loop {
+        while a_chunk.is_empty() {
+            match a.next() {
+                Some(chunk) => a_chunk = chunk,
+                None => break,
+            }
+        }
+        while b_chunk.is_empty() {
+            match b.next() {
+                Some(chunk) => b_chunk = chunk,
+                None => break,
+            }
+        }
+        if a_chunk.is_empty() || b_chunk.is_empty() {
+            return (!a_chunk.is_empty()).cmp(&!b_chunk.is_empty());
+        }
+
+        let len = a_chunk.len().min(b_chunk.len());
+        match a_chunk[..len].cmp(&b_chunk[..len]) {
+            Ordering::Equal => {
+                a_chunk = &a_chunk[len..];
+                b_chunk = &b_chunk[len..];
+            }
+            ordering => return ordering,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+
+    use super::{FsstColumn, SymbolTable};
+    use crate::arc::UmbraArcString;
+
+    fn urls() -> Vec<UmbraArcString> {
+        (0..500)
+            .map(|i| {
+                UmbraArcString::new(format!(
+                    "https://www.example.com/products/{}?ref=newsletter",
+                    i * 7
+                ))
+            })
+            .collect()
+    }
+
+    #[test]
+    fn round_trip_test() {
+        let mut values = urls();
+        values.extend(
+            [
+                "",
+                "tiny",
+                "twelve bytes",
+                "ünïcödé strings which are quite long",
+            ]
+            .map(UmbraArcString::new),
+        );
+        let column = FsstColumn::compress(&values);
+
+        assert_eq!(column.len(), values.len());
+        for (compressed, val) in column.iter().zip(&values) {
+            assert_eq!(compressed.to_umbra(), *val);
+            assert_eq!(compressed, *val);
+        }
+        assert_eq!(column.get(250).unwrap().to_umbra(), values[250]);
+    }
+
+    #[test]
+    fn compression_test() {
+        let values = urls();
+        let column = FsstColumn::compress(&values);
+        let raw: usize = values.iter().map(|val| val.len() - 4).sum();
+
+        assert!(!column.table().is_empty());
+        assert!(
+            column.compressed_size() * 2 < raw,
+            "{} of {raw}",
+            column.compressed_size()
+        );
+    }
+
+    #[test]
+    fn untrained_bytes_test() {
+        let table = SymbolTable::train(&[UmbraArcString::new("aaaaaaaaaaaaaaaaaaaa")]);
+        let mut compressed = Vec::new();
+        table.compress(b"aaaazzzz\xff", &mut compressed);
+
+        let mut decompressed = Vec::new();
+        table.decompress(&compressed, &mut decompressed);
+        assert_eq!(decompressed, b"aaaazzzz\xff");
+    }
+
+    #[test]
+    fn cmp_test() {
+        let values: Vec<UmbraArcString> = [
+            "https://b.example.com/a",
+            "https://a.example.com/b",
+            "https://a.example.com/b",
+            "http",
+            "htt",
+        ]
+        .map(UmbraArcString::new)
+        .to_vec();
+        let column = FsstColumn::compress(&values);
+        let compressed: Vec<_> = column.iter().collect();
+
+        for (a, a_val) in compressed.iter().zip(&values) {
+            for (b, b_val) in compressed.iter().zip(&values) {
+                assert_eq!(a.cmp(b), a_val.cmp(b_val));
+                assert_eq!(a == b, a_val == b_val);
+                assert_eq!(a.partial_cmp(b_val), Some(a_val.cmp(b_val)));
+            }
+        }
+    }
+
+    #[test]
+    fn across_columns_test() {
+        let values = urls();
+        let table = Arc::new(SymbolTable::train(&values));
+        let mut shared = FsstColumn::new(table.clone());
+        let mut other = FsstColumn::new(SymbolTable::train(&values[..3]));
+        for val in &values {
+            shared.push(val);
+            other.push(val);
+        }
+        assert!(Arc::ptr_eq(shared.table(), &table));
+
+        let mut column = FsstColumn::new(table);
+        let short = [
+            "https://www",
+            "https://www.example.com/products/7",
+            "https://www.example.com/products/7?ref=newsletter!",
+            "https://www.example.com/products/7?ref=newsletter",
+        ]
+        .map(UmbraArcString::new);
+        for val in &short {
+            column.push(val);
+        }
+        assert_eq!(column.get(3), shared.get(1));
+        assert_ne!(column.get(2), shared.get(1));
+
+        for (i, a_val) in values.iter().enumerate().step_by(37) {
+            for (j, b_val) in values.iter().enumerate().step_by(41) {
+                let expected = a_val.cmp(b_val);
+                assert_eq!(shared.get(i).unwrap().cmp(&other.get(j).unwrap()), expected);
+                assert_eq!(shared.get(i) == other.get(j), a_val == b_val);
+            }
+            for (j, b_val) in short.iter().enumerate() {
+                assert_eq!(
+                    column.get(j).unwrap().cmp(&shared.get(i).unwrap()),
+                    b_val.cmp(a_val)
+                );
+                assert_eq!(
+                    other.get(i).unwrap().cmp(&column.get(j).unwrap()),
+                    a_val.cmp(b_val)
+                );
+                assert_eq!(column.get(j) == shared.get(i), b_val == a_val);
+            }
+        }
+    }
+}
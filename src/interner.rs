@@ -0,0 +1,133 @@
+//! Interning of long strings, so repeated values share one allocation rather than each getting their own
+
+use std::{
+    collections::HashSet,
+    hash::{BuildHasher, RandomState},
+    sync::{Mutex, MutexGuard, PoisonError},
+};
+
+use crate::{
+    arc::{UmbraArcString, MAX_INLINE},
+    common, UmbraError,
+};
+
+/// Number of independently locked parts of the table, so threads interning different strings rarely contend
+const SHARDS: usize = 16;
+
+/// A concurrent table of interned strings, each returned string is a clone sharing the allocation of the entry
+#[derive(Debug, Default)]
+pub struct UmbraInterner {
+    hasher: RandomState,
+    shards: [Mutex<HashSet<UmbraArcString>>; SHARDS],
+}
+
+impl UmbraInterner {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Gets the shared copy of a string, inline strings are never stored as they don't allocate anyway
+    ///
+    /// Panics if the string is longer than u32::MAX bytes
+    pub fn intern(&self, val: &str) -> UmbraArcString {
+        common::unwrap_new(self.try_intern(val))
+    }
+
+    pub fn try_intern(&self, val: &str) -> Result<UmbraArcString, UmbraError> {
+        if val.len() <= MAX_INLINE {
+            return UmbraArcString::try_new(val);
+        }
+
+        let mut shard = self.shard(val);
+        if let Some(interned) = shard.get(val) {
+            return Ok(interned.clone());
+        }
+        let interned = UmbraArcString::try_new(val)?;
+        shard.insert(interned.clone());
+        Ok(interned)
+    }
+
+    /// Drops the entries which are no longer used outside the interner, returning how many were dropped
+    pub fn evict_unused(&self) -> usize {
+        let mut evicted = 0;
+        for shard in &self.shards {
+            let mut shard = shard.lock().unwrap_or_else(PoisonError::into_inner);
+            let before = shard.len();
+            // an entry only referenced by the table can't gain a reference while its shard is locked
+            shard.retain(|interned| interned.strong_count() != Some(1));
+            evicted += before - shard.len();
+        }
+        evicted
+    }
+
+    /// The number of strings in the table
+    pub fn len(&self) -> usize {
+        self.shards
+            .iter()
+            .map(|shard| shard.lock().unwrap_or_else(PoisonError::into_inner).len())
+            .sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    fn shard(&self, val: &str) -> MutexGuard<'_, HashSet<UmbraArcString>> {
+        let shard = &self.shards[self.hasher.hash_one(val) as usize % SHARDS];
+        // the table is never left half updated, so it is still usable after a panic
+        shard.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::thread;
+
+    use super::UmbraInterner;
+
+    const LONG: &str = "a string which is too long to inline";
+
+    #[test]
+    fn intern_test() {
+        let interner = UmbraInterner::new();
+        let a = interner.intern(LONG);
+        let b = interner.intern(&String::from(LONG));
+        let short = interner.intern("short");
+
+        assert_eq!(a, b);
+        assert_eq!(a.as_ptr(), b.as_ptr());
+        assert_eq!(short, "short");
+        assert_eq!(interner.len(), 1);
+    }
+
+    #[test]
+    fn evict_test() {
+        let interner = UmbraInterner::new();
+        let kept = interner.intern(LONG);
+        drop(interner.intern("another string too long to inline"));
+
+        assert_eq!(interner.evict_unused(), 1);
+        assert_eq!(interner.len(), 1);
+        drop(kept);
+        assert_eq!(interner.evict_unused(), 1);
+        assert!(interner.is_empty());
+    }
+
+    #[test]
+    fn concurrent_test() {
+        let interner = UmbraInterner::new();
+        thread::scope(|scope| {
+            for _ in 0..4 {
+                scope.spawn(|| {
+                    for i in 0..1000 {
+                        let val = format!("{LONG} {}", i % 100);
+                        assert_eq!(interner.intern(&val), &*val);
+                    }
+                });
+            }
+        });
+
+        assert_eq!(interner.len(), 100);
+        assert_eq!(interner.evict_unused(), 100);
+    }
+}
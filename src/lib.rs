@@ -1 +1,45 @@
 pub mod arc;
+#[cfg(target_endian = "little")]
+pub mod arrow;
+#[cfg(target_pointer_width = "64")]
+pub mod atomic;
+pub mod borrowed;
+pub mod boxed;
+pub mod bytes;
+pub mod collation;
+mod common;
+pub mod dictionary;
+mod error;
+pub mod fsst;
+pub mod interner;
+pub mod like;
+pub mod rc;
+#[cfg(feature = "serde")]
+mod serde_impl;
+pub mod sort;
+pub mod sort_key;
+mod sync;
+pub mod vec;
+
+pub use common::UmbraStrMut;
+pub use error::UmbraError;
+
+/// Checks at compile time that the types meant to be shared between threads are Send and Sync. UmbraRcString is left
+/// out as its refcount isn't atomic, so it is neither
+const _: () = {
+    const fn assert_send_sync<T: Send + Sync>() {}
+
+    assert_send_sync::<arc::UmbraArcString>();
+    assert_send_sync::<arc::UmbraWeakString>();
+    #[cfg(target_pointer_width = "64")]
+    assert_send_sync::<atomic::AtomicUmbraString>();
+    assert_send_sync::<borrowed::UmbraStr<'static>>();
+    assert_send_sync::<boxed::UmbraBoxString>();
+    assert_send_sync::<bytes::UmbraArcBytes>();
+    assert_send_sync::<dictionary::DictionaryColumn>();
+    assert_send_sync::<fsst::FsstColumn>();
+    assert_send_sync::<interner::UmbraInterner>();
+    assert_send_sync::<vec::UmbraStringVec>();
+    #[cfg(target_endian = "little")]
+    assert_send_sync::<arrow::StringViewArray>();
+};
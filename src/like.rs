@@ -0,0 +1,367 @@
+//! Compiled SQL LIKE, ILIKE and GLOB patterns. Patterns starting with a literal check it against the prefix in the
+//! header first, which rejects most strings and fully decides patterns like `'abc%'` without following the pointer.
+
+use crate::{arc::UmbraArcString, collation::Collation, common::UmbraLayout, UmbraError};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Token {
+    Literal(String),
+    /// `_` or `?`, exactly one char
+    One,
+    /// `%` or `*`, any number of chars
+    Any,
+    /// A GLOB `[...]` class of inclusive char ranges
+    Class {
+        ranges: Vec<(char, char)>,
+        negated: bool,
+    },
+}
+
+impl Token {
+    fn class_matches(ranges: &[(char, char)], negated: bool, c: char) -> bool {
+        ranges
+            .iter()
+            .any(|&(start, end)| (start..=end).contains(&c))
+            != negated
+    }
+}
+
+/// A compiled pattern, matched against whole strings
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LikePattern {
+    tokens: Vec<Token>,
+    /// Folds both the pattern and the strings matched with Collation::CASE_INSENSITIVE
+    case_insensitive: bool,
+    /// Fewest bytes a matching string can have. Folding can change how many bytes a char takes but not how many chars
+    /// there are, so for ILIKE this counts the chars of the literals
+    min_len: usize,
+}
+
+fn invalid(reason: &'static str) -> UmbraError {
+    UmbraError::InvalidPattern { reason }
+}
+
+/// Builds up tokens, merging adjacent literals and repeated `%`
+#[derive(Default)]
+struct Builder {
+    tokens: Vec<Token>,
+}
+
+impl Builder {
+    fn literal(&mut self, c: char) {
+        match self.tokens.last_mut() {
+            Some(Token::Literal(literal)) => literal.push(c),
+            _ => self.tokens.push(Token::Literal(c.to_string())),
+        }
+    }
+
+    fn push(&mut self, token: Token) {
+        if !(token == Token::Any && self.tokens.last() == Some(&Token::Any)) {
+            self.tokens.push(token);
+        }
+    }
+
+    fn build(self, case_insensitive: bool) -> LikePattern {
+        let min_len = self
+            .tokens
+            .iter()
+            .map(|token| match token {
+                Token::Literal(literal) if case_insensitive => literal.chars().count(),
+                Token::Literal(literal) => literal.len(),
+                Token::One | Token::Class { .. } => 1,
+                Token::Any => 0,
+            })
+            .sum();
+        LikePattern {
+            tokens: self.tokens,
+            case_insensitive,
+            min_len,
+        }
+    }
+}
+
+impl LikePattern {
+    /// Compiles a LIKE pattern, where `%` matches any sequence of chars, `_` matches one char, and the escape char
+    /// makes the char after it literal
+    pub fn like(pattern: &str, escape: Option<char>) -> Result<Self, UmbraError> {
+        Self::parse_like(pattern, escape, false)
+    }
+
+    /// Compiles an ILIKE pattern, which is a LIKE pattern matched ignoring case with Unicode simple case folding
+    pub fn ilike(pattern: &str, escape: Option<char>) -> Result<Self, UmbraError> {
+        Self::parse_like(pattern, escape, true)
+    }
+
+    fn parse_like(
+        pattern: &str,
+        escape: Option<char>,
+        case_insensitive: bool,
+    ) -> Result<Self, UmbraError> {
+        let mut builder = Builder::default();
+        let mut chars = pattern.chars();
+        while let Some(c) = chars.next() {
+            match c {
+                _ if Some(c) == escape => {
+                    let escaped = chars
+                        .next()
+                        .ok_or(invalid("pattern ends with the escape char"))?;
+                    builder.literal(escaped);
+                }
+                '%' => builder.push(Token::Any),
+                '_' => builder.push(Token::One),
+                _ => builder.literal(c),
+            }
+        }
+
+        if case_insensitive {
+            for token in &mut builder.tokens {
+                if let Token::Literal(literal) = token {
+                    *literal = Collation::CASE_INSENSITIVE.fold(literal).collect();
+                }
+            }
+        }
+        Ok(builder.build(case_insensitive))
+    }
+
+    /// Compiles a case-sensitive GLOB pattern, where `*` matches any sequence of chars, `?` matches one char, and
+    /// `[...]` matches one char from a set of chars and ranges, or not in it when it starts with `^`
+    pub fn glob(pattern: &str) -> Result<Self, UmbraError> {
+        let mut builder = Builder::default();
+        let mut chars = pattern.chars().peekable();
+        while let Some(c) = chars.next() {
+            match c {
+                '*' => builder.push(Token::Any),
+                '?' => builder.push(Token::One),
+                '[' => {
+                    let negated = chars.next_if_eq(&'^').is_some();
+                    let mut ranges = Vec::new();
+                    // a ] straight after the [ or ^ is part of the class
+                    let mut first = true;
+                    loop {
+                        let start = chars.next().ok_or(invalid("unterminated [ in glob"))?;
+                        if start == ']' && !first {
+                            break;
+                        }
+                        first = false;
+                        let end = match chars.peek() {
+                            Some('-') => {
+                                chars.next();
+                                match chars.next_if(|&c| c != ']') {
+                                    Some(end) => end,
+                                    None => {
+                                        ranges.push(('-', '-'));
+                                        start
+                                    }
+                                }
+                            }
+                            _ => start,
+                        };
+                        ranges.push((start, end));
+                    }
+                    builder.push(Token::Class { ranges, negated });
+                }
+                _ => builder.literal(c),
+            }
+        }
+        Ok(builder.build(false))
+    }
+
+    /// The literal every match starts with, after folding for ILIKE
+    fn literal_prefix(&self) -> &str {
+        match self.tokens.first() {
+            Some(Token::Literal(literal)) => literal,
+            _ => "",
+        }
+    }
+
+    pub fn matches(&self, val: &UmbraArcString) -> bool {
+        if self.case_insensitive {
+            return self.matches_folded(val);
+        }
+
+        // a string too short or whose prefix doesn't start with the literal can't match, which only needs the header
+        let literal = self.literal_prefix().as_bytes();
+        let checked = literal.len().min(4);
+        if val.len() < self.min_len || val.raw_prefix()[..checked] != literal[..checked] {
+            return false;
+        }
+
+        match self.tokens.as_slice() {
+            [Token::Any] => true,
+            [Token::Literal(_)] => {
+                val.len() == literal.len()
+                    && val.suffix_bytes() == literal.get(4..).unwrap_or_default()
+            }
+            [Token::Literal(_), Token::Any] => {
+                literal.len() <= 4 || val.suffix_bytes().starts_with(&literal[4..])
+            }
+            tokens => match_tokens(tokens, val.chars()),
+        }
+    }
+
+    fn matches_folded(&self, val: &UmbraArcString) -> bool {
+        if val.len() < self.min_len {
+            return false;
+        }
+
+        // while both are ASCII they fold byte by byte, past that a char could fold to one of a different length or to
+        // an ASCII one, like ſ to s. Strings passing this are folded char by char as they are matched
+        let literal = self.literal_prefix().as_bytes();
+        let prefix = val.raw_prefix();
+        let checked = literal.len().min(val.len()).min(4);
+        let ascii = literal[..checked]
+            .iter()
+            .zip(&prefix[..checked])
+            .take_while(|(pattern_byte, byte)| pattern_byte.is_ascii() && byte.is_ascii())
+            .count();
+        if !literal[..ascii].eq_ignore_ascii_case(&prefix[..ascii]) {
+            return false;
+        }
+
+        match_tokens(&self.tokens, Collation::CASE_INSENSITIVE.fold(val))
+    }
+}
+
+/// Matches greedily, retrying from the last `%` on a mismatch. Each literal matches at most one way at a given position,
+/// so a later `%` can always do whatever an earlier one could and only the last needs retrying
+fn match_tokens(tokens: &[Token], val: impl Iterator<Item = char> + Clone) -> bool {
+    let (mut token, mut rest) = (0, val);
+    let mut retry = None;
+    loop {
+        let mut after = rest.clone();
+        let matched = match (tokens.get(token), after.next()) {
+            (None, None) => return true,
+            (Some(Token::Any), _) => {
+                retry = Some((token + 1, rest.clone()));
+                after = rest.clone();
+                true
+            }
+            (Some(Token::Literal(literal)), _) => {
+                after = rest.clone();
+                literal.chars().all(|c| after.next() == Some(c))
+            }
+            (Some(Token::One), Some(_)) => true,
+            (Some(Token::Class { ranges, negated }), Some(c)) => {
+                Token::class_matches(ranges, *negated, c)
+            }
+            _ => false,
+        };
+
+        if matched {
+            token += 1;
+            rest = after;
+            continue;
+        }
+        // let the last % take one more char and try again after it
+        let Some((retry_token, retry_rest)) = &mut retry else {
+            return false;
+        };
+        if retry_rest.next().is_none() {
+            return false;
+        }
+        token = *retry_token;
+        rest = retry_rest.clone();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::LikePattern;
+    use crate::{arc::UmbraArcString, UmbraError};
+
+    fn like(pattern: &str, val: &str) -> bool {
+        LikePattern::like(pattern, Some('\\'))
+            .unwrap()
+            .matches(&UmbraArcString::new(val))
+    }
+
+    #[test]
+    fn like_test() {
+        assert!(like("abc%", "abc"));
+        assert!(like("abc%", "abcdefghijklmnopqrstuvwxyz"));
+        assert!(!like("abc%", "ab"));
+        assert!(!like("abc%", "xbcdefghijklmnopqrstuvwxyz"));
+        assert!(like("abcdefgh%", "abcdefghijklmnopqrstuvwxyz"));
+        assert!(!like("abcdefgx%", "abcdefghijklmnopqrstuvwxyz"));
+        assert!(like(
+            "a string which is too long to inline",
+            "a string which is too long to inline"
+        ));
+        assert!(!like(
+            "a string which is too long to inline",
+            "a string which is too long to inlinE"
+        ));
+
+        assert!(like("%", ""));
+        assert!(like("%xyz", "abcdefghijklmnopqrstuvwxyz"));
+        assert!(like("%mno%", "abcdefghijklmnopqrstuvwxyz"));
+        assert!(like("a%m%z", "abcdefghijklmnopqrstuvwxyz"));
+        assert!(!like("a%m%y", "abcdefghijklmnopqrstuvwxyz"));
+        assert!(like("%ab%ab%", "xxabyyab"));
+        assert!(like("_b_", "abc"));
+        assert!(like("_b_", "ébç"));
+        assert!(!like("_b_", "abcd"));
+        assert!(like("%_", "é"));
+    }
+
+    #[test]
+    fn escape_test() {
+        assert!(like("100\\%", "100%"));
+        assert!(!like("100\\%", "1000"));
+        assert!(like("a\\_b%", "a_bc"));
+        assert!(!like("a\\_b%", "axbc"));
+        assert!(LikePattern::like("%", None)
+            .unwrap()
+            .matches(&UmbraArcString::new("\\")));
+        assert_eq!(
+            LikePattern::like("abc\\", Some('\\')).unwrap_err(),
+            UmbraError::InvalidPattern {
+                reason: "pattern ends with the escape char"
+            }
+        );
+    }
+
+    #[test]
+    fn ilike_test() {
+        let pattern = LikePattern::ilike("ABC%", None).unwrap();
+        assert!(pattern.matches(&UmbraArcString::new("abcdefghijklmnop")));
+        assert!(pattern.matches(&UmbraArcString::new("AbC")));
+        assert!(!pattern.matches(&UmbraArcString::new("abd")));
+        assert!(!pattern.matches(&UmbraArcString::new("ab")));
+
+        // ſ folds to s and Ⱥ to a longer ⱥ, neither of which the ASCII prefix check or min_len may reject
+        let pattern = LikePattern::ilike("stRASSE", None).unwrap();
+        assert!(pattern.matches(&UmbraArcString::new("ſtrasse")));
+        assert!(!pattern.matches(&UmbraArcString::new("strass")));
+        let pattern = LikePattern::ilike("ⱥ_", None).unwrap();
+        assert!(pattern.matches(&UmbraArcString::new("Ⱥx")));
+
+        let pattern = LikePattern::ilike("%straße_", None).unwrap();
+        assert!(pattern.matches(&UmbraArcString::new("HAUPTSTRAẞE1")));
+
+        // the micro sign and both cases of mu all fold to μ
+        let pattern = LikePattern::ilike("\u{b5}%", None).unwrap();
+        assert!(pattern.matches(&UmbraArcString::new("Μ")));
+        assert!(pattern.matches(&UmbraArcString::new("μs")));
+        assert!(!pattern.matches(&UmbraArcString::new("M")));
+    }
+
+    #[test]
+    fn glob_test() {
+        let glob = |pattern: &str, val: &str| {
+            LikePattern::glob(pattern)
+                .unwrap()
+                .matches(&UmbraArcString::new(val))
+        };
+        assert!(glob("*.rs", "a/path/to/some/module.rs"));
+        assert!(!glob("*.rs", "a/path/to/some/module.RS"));
+        assert!(glob("file?.[ch]", "file1.c"));
+        assert!(!glob("file?.[ch]", "file1.o"));
+        assert!(glob("[^0-9]*", "x1"));
+        assert!(!glob("[^0-9]*", "1x"));
+        assert!(glob("[]a]", "]"));
+        assert!(glob("[a-]", "-"));
+        assert!(glob("100%", "100%"));
+        assert!(LikePattern::glob("[abc").is_err());
+    }
+}
@@ -0,0 +1,327 @@
+use core::str;
+use std::{alloc, cell::Cell, mem::ManuallyDrop, ops::Deref, ptr};
+
+use crate::{
+    arc::{UmbraArcString, MAX_INLINE},
+    common::{self, impl_umbra_str_traits, UmbraLayout, HEADER_SIZE, RESERVED_WORDS},
+};
+
+/// An owned non-atomically reference counted Umbra-style string, for use within a single thread
+///
+/// It is deliberately neither Send nor Sync, as clones on different threads would race on the refcount.
+#[repr(C)]
+pub struct UmbraRcString {
+    len: u32,
+    prefix: [u8; 4],
+    extra: UmbraRcExtra,
+}
+
+pub union UmbraRcExtra {
+    data: [u8; 8],
+    ptr: *const u8,
+}
+
+/// Header at the start of every out-of-line allocation, the string bytes follow directly after it
+#[repr(C)]
+struct RcHeader {
+    count: Cell<usize>,
+    /// Pads the header to HEADER_SIZE, UmbraArcString keeps its weak count and the allocation length here
+    _reserved: [usize; RESERVED_WORDS],
+}
+
+const _: () = assert!(size_of::<RcHeader>() == HEADER_SIZE);
+
+impl UmbraRcString {
+    /// Panics if the string is longer than u32::MAX bytes
+    pub fn new(val: impl AsRef<str>) -> UmbraRcString {
+        let val_str = val.as_ref();
+        let len = common::unwrap_new(common::checked_len(val_str.len()));
+
+        if len as usize <= MAX_INLINE {
+            let (prefix, extra) = common::split_inline(val_str.as_bytes());
+
+            UmbraRcString {
+                len,
+                prefix,
+                extra: UmbraRcExtra { data: extra },
+            }
+        } else {
+            UmbraRcString {
+                len,
+                prefix: common::heap_prefix(val_str.as_bytes()),
+                extra: UmbraRcExtra::inner_ptr_new(val_str),
+            }
+        }
+    }
+
+    pub fn is_inline(&self) -> bool {
+        self.len <= MAX_INLINE as u32
+    }
+
+    pub fn len(&self) -> usize {
+        self.len as usize
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Converts into an atomically reference counted string without copying, if this is the only reference to the
+    /// heap buffer
+    pub fn try_into_arc(self) -> Result<UmbraArcString, Self> {
+        if self.is_inline() {
+            return Ok(UmbraArcString::new(&*self));
+        }
+
+        // SAFETY: !is_inline() so ptr is active
+        if unsafe { self.extra.inner_ptr_header() }.count.get() != 1 {
+            return Err(self);
+        }
+
+        let this = ManuallyDrop::new(self);
+        // SAFETY: this was the only reference to an allocation from common::heap_alloc holding len bytes of utf-8, and
+        // this is no longer dropped
+        Ok(unsafe {
+            UmbraArcString::from_unique_heap(
+                this.len,
+                this.prefix,
+                this.extra.ptr.cast_mut(),
+                this.len,
+            )
+        })
+    }
+
+    /// Converts an atomically reference counted string without copying, if it is the only reference to the heap buffer.
+    /// Persistent and transient strings have no buffer to take over, so are always given back, as are substrings and
+    /// strings from an UmbraBoxString with spare capacity, whose buffer holds more than their bytes
+    pub fn try_from_arc(value: UmbraArcString) -> Result<Self, UmbraArcString> {
+        if value.is_inline() {
+            return Ok(UmbraRcString::new(&*value));
+        }
+
+        let len = value.raw_len();
+        let prefix = value.raw_prefix();
+        let ptr = value.try_into_unique_heap()?;
+
+        // SAFETY: ptr is now uniquely owned and has room for the header, the bytes are kept as they are
+        unsafe {
+            ptr.cast::<RcHeader>().write(RcHeader {
+                count: Cell::new(1),
+                _reserved: [0; RESERVED_WORDS],
+            })
+        };
+
+        Ok(UmbraRcString {
+            len,
+            prefix,
+            extra: UmbraRcExtra { ptr },
+        })
+    }
+}
+
+impl TryFrom<UmbraRcString> for UmbraArcString {
+    type Error = UmbraRcString;
+
+    fn try_from(value: UmbraRcString) -> Result<Self, Self::Error> {
+        value.try_into_arc()
+    }
+}
+
+impl TryFrom<UmbraArcString> for UmbraRcString {
+    type Error = UmbraArcString;
+
+    fn try_from(value: UmbraArcString) -> Result<Self, Self::Error> {
+        UmbraRcString::try_from_arc(value)
+    }
+}
+
+impl UmbraLayout for UmbraRcString {
+    #[inline]
+    fn raw_len(&self) -> u32 {
+        self.len
+    }
+
+    #[inline]
+    fn raw_prefix(&self) -> [u8; 4] {
+        self.prefix
+    }
+
+    #[inline]
+    fn inline_data(&self) -> Option<&[u8; 8]> {
+        if self.is_inline() {
+            // SAFETY: is_inline() so data is active
+            Some(unsafe { &self.extra.data })
+        } else {
+            None
+        }
+    }
+
+    #[inline]
+    fn suffix_bytes(&self) -> &[u8] {
+        self.as_bytes().get(4..).unwrap_or_default()
+    }
+}
+
+impl Clone for UmbraRcString {
+    fn clone(&self) -> Self {
+        if self.is_inline() {
+            Self {
+                len: self.len,
+                prefix: self.prefix,
+                // SAFETY: is_inline() so data is active
+                extra: unsafe { self.extra.inner_data_clone() },
+            }
+        } else {
+            Self {
+                len: self.len,
+                prefix: self.prefix,
+                // SAFETY: !is_inline() so ptr is active
+                extra: unsafe { self.extra.inner_ptr_clone() },
+            }
+        }
+    }
+}
+
+impl Deref for UmbraRcString {
+    type Target = str;
+
+    fn deref(&self) -> &Self::Target {
+        let bytes = if self.is_inline() {
+            // SAFETY: UmbraRcString has the Umbra layout and is_inline() so data is active
+            unsafe { common::inline_bytes(self, self.len) }
+        } else {
+            // SAFETY: !is_inline() so ptr is active
+            unsafe { self.extra.inner_ptr_bytes(self.len) }
+        };
+        // SAFETY: bytes were copied from a str, so should be valid utf-8
+        unsafe { str::from_utf8_unchecked(bytes) }
+    }
+}
+
+impl_umbra_str_traits!(UmbraRcString);
+
+impl Drop for UmbraRcString {
+    fn drop(&mut self) {
+        if !self.is_inline() {
+            // SAFETY: !is_inline() so ptr is active, ptr is private and created with inner_ptr_new
+            unsafe { self.extra.inner_ptr_drop(self.len) }
+        }
+    }
+}
+
+impl UmbraRcExtra {
+    fn inner_ptr_new(val: &str) -> Self {
+        let ptr = common::heap_alloc(val.len());
+
+        // SAFETY: ptr was just allocated with room for the header followed by val.len() bytes
+        unsafe {
+            ptr.cast::<RcHeader>().write(RcHeader {
+                count: Cell::new(1),
+                _reserved: [0; RESERVED_WORDS],
+            });
+            ptr::copy_nonoverlapping(val.as_ptr(), ptr.add(HEADER_SIZE), val.len());
+        }
+
+        Self { ptr }
+    }
+
+    /// SAFETY: Must be called with ptr field active and with the length of the string it was created with
+    unsafe fn inner_ptr_bytes(&self, len: u32) -> &[u8] {
+        // SAFETY: ptr must be active under preconditions, the bytes follow the header and live as long as self
+        unsafe { &*ptr::slice_from_raw_parts(self.ptr.add(HEADER_SIZE), len as usize) }
+    }
+
+    /// SAFETY: Must be called with ptr field active
+    unsafe fn inner_ptr_header(&self) -> &RcHeader {
+        // SAFETY: ptr must be active under preconditions and points to a header
+        unsafe { &*self.ptr.cast::<RcHeader>() }
+    }
+
+    /// SAFETY: Must be called with ptr field active and it containing a pointer from inner_ptr_new
+    unsafe fn inner_ptr_clone(&self) -> Self {
+        // SAFETY: ptr must be active under preconditions
+        let header = unsafe { self.inner_ptr_header() };
+
+        let count = header.count.get();
+        if count == usize::MAX {
+            std::process::abort();
+        }
+        header.count.set(count + 1);
+
+        UmbraRcExtra {
+            // SAFETY: ptr must be active under preconditions
+            ptr: unsafe { self.ptr },
+        }
+    }
+
+    /// SAFETY: Must be called with data field active
+    unsafe fn inner_data_clone(&self) -> Self {
+        UmbraRcExtra {
+            // SAFETY: data must be active under preconditions
+            data: unsafe { self.data },
+        }
+    }
+
+    /// SAFETY: Must be called with ptr field active and it containing a pointer from inner_ptr_new, with the length of the string it was created with
+    unsafe fn inner_ptr_drop(&self, len: u32) {
+        // SAFETY: ptr must be active under preconditions
+        let header = unsafe { self.inner_ptr_header() };
+
+        let count = header.count.get() - 1;
+        header.count.set(count);
+        if count != 0 {
+            return;
+        }
+
+        // SAFETY: this was the last reference and the allocation was made in inner_ptr_new with this layout
+        unsafe { alloc::dealloc(self.ptr.cast_mut(), common::heap_layout(len as usize)) }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::UmbraRcString;
+    use crate::arc::UmbraArcString;
+
+    #[test]
+    fn basic_test() {
+        let inline = UmbraRcString::new("abcdefghijkl");
+        let heap = UmbraRcString::new("abcdefghijklmnopqr");
+
+        assert_eq!(inline, "abcdefghijkl");
+        assert_eq!(heap, "abcdefghijklmnopqr");
+        assert!(inline < heap);
+    }
+
+    #[test]
+    fn clone_drop_test() {
+        let umbra = UmbraRcString::new("a string that lives on the heap");
+        let cloned = umbra.clone();
+        drop(umbra);
+
+        assert_eq!(cloned, "a string that lives on the heap");
+        assert_eq!(cloned.clone(), cloned);
+    }
+
+    #[test]
+    fn arc_conversion_test() {
+        let rc = UmbraRcString::new("a string that lives on the heap");
+        let shared = rc.clone();
+        let rc = UmbraArcString::try_from(rc).unwrap_err();
+        drop(shared);
+
+        let arc = UmbraArcString::try_from(rc).unwrap();
+        assert_eq!(arc, "a string that lives on the heap");
+
+        let shared = arc.clone();
+        let arc = UmbraRcString::try_from(arc).unwrap_err();
+        drop(shared);
+
+        let rc = UmbraRcString::try_from(arc).unwrap();
+        assert_eq!(rc, "a string that lives on the heap");
+        assert_eq!(
+            UmbraRcString::try_from(UmbraArcString::new("short")).unwrap(),
+            "short"
+        );
+    }
+}
@@ -0,0 +1,98 @@
+//! Serde support, behind the serde feature. Strings serialize as plain strings, and deserialize straight from the
+//! deserializer's str into the inline form or a single allocation.
+
+use std::fmt;
+
+use serde::{
+    de::{self, Visitor},
+    Deserialize, Deserializer, Serialize, Serializer,
+};
+
+use crate::arc::UmbraArcString;
+
+impl Serialize for UmbraArcString {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self)
+    }
+}
+
+struct UmbraArcStringVisitor;
+
+impl UmbraArcStringVisitor {
+    /// Copies val into the string's own storage, which for up to MAX_INLINE bytes is the header itself
+    fn build<E: de::Error>(val: &str) -> Result<UmbraArcString, E> {
+        UmbraArcString::try_new(val).map_err(E::custom)
+    }
+}
+
+impl<'de> Visitor<'de> for UmbraArcStringVisitor {
+    type Value = UmbraArcString;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("a string")
+    }
+
+    fn visit_str<E: de::Error>(self, v: &str) -> Result<Self::Value, E> {
+        Self::build(v)
+    }
+
+    fn visit_borrowed_str<E: de::Error>(self, v: &'de str) -> Result<Self::Value, E> {
+        Self::build(v)
+    }
+
+    /// The String's buffer has no room for the header, so its bytes are copied once and it is dropped
+    fn visit_string<E: de::Error>(self, v: String) -> Result<Self::Value, E> {
+        Self::build(&v)
+    }
+
+    fn visit_bytes<E: de::Error>(self, v: &[u8]) -> Result<Self::Value, E> {
+        match std::str::from_utf8(v) {
+            Ok(val) => Self::build(val),
+            Err(_) => Err(E::invalid_value(de::Unexpected::Bytes(v), &self)),
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for UmbraArcString {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_str(UmbraArcStringVisitor)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use serde::{
+        de::value::{self, BorrowedStrDeserializer},
+        Deserialize,
+    };
+    use serde_test::{assert_de_tokens, assert_de_tokens_error, assert_tokens, Token};
+
+    use crate::arc::UmbraArcString;
+
+    #[test]
+    fn round_trip_test() {
+        assert_tokens(&UmbraArcString::new("short"), &[Token::Str("short")]);
+        assert_tokens(
+            &UmbraArcString::new("a string which is too long to inline"),
+            &[Token::Str("a string which is too long to inline")],
+        );
+    }
+
+    #[test]
+    fn deserialize_test() {
+        const LONG: &str = "a string which is too long to inline";
+        let long = UmbraArcString::new(LONG);
+        assert_de_tokens(&long, &[Token::BorrowedStr(LONG)]);
+        assert_de_tokens(&long, &[Token::String(LONG)]);
+        assert_de_tokens(&long, &[Token::Bytes(LONG.as_bytes())]);
+        assert_de_tokens_error::<UmbraArcString>(
+            &[Token::Bytes(b"\xff")],
+            "invalid value: byte array, expected a string",
+        );
+
+        let de = BorrowedStrDeserializer::<value::Error>::new("abcdefghijkl");
+        let inline = UmbraArcString::deserialize(de).unwrap();
+        assert!(inline.is_inline());
+        assert_eq!(inline, "abcdefghijkl");
+    }
+}
@@ -0,0 +1,153 @@
+//! Sorting slices of Umbra strings. Most of the order is decided by the prefix and length held in the headers, so those
+//! are radix sorted first without following any pointers, and only runs of strings which tie on them compare the rest
+//! of their bytes.
+
+use crate::{arc::UmbraArcString, common::UmbraLayout};
+
+/// Below this a comparison sort is faster than the radix passes
+const SMALL_SORT: usize = 32;
+
+/// The part of the order given by the header. Strings of up to 4 bytes are the prefix zero padded, so they sort by
+/// prefix then length, and are a prefix of and so before any longer string with the same prefix. Longer strings share
+/// the length class 5 and tie on this key.
+fn radix_key(val: &UmbraArcString) -> u64 {
+    let class = val.raw_len().min(5);
+    u64::from(u32::from_be_bytes(val.raw_prefix())) << 8 | u64::from(class)
+}
+
+/// Whether strings with this key need their suffixes compared to be ordered
+fn key_ties(key: u64) -> bool {
+    key & 0xff == 5
+}
+
+/// Sorts strings in ascending order, like `slice::sort_unstable` but dereferencing only strings whose prefixes tie
+pub fn sort_unstable(slice: &mut [UmbraArcString]) {
+    if slice.len() < SMALL_SORT {
+        slice.sort_unstable();
+        return;
+    }
+
+    let mut keys: Vec<(u64, usize)> = slice
+        .iter()
+        .enumerate()
+        .map(|(index, val)| (radix_key(val), index))
+        .collect();
+    radix_sort(&mut keys);
+
+    let mut order: Vec<usize> = keys.iter().map(|&(_, index)| index).collect();
+    permute(slice, &mut order);
+
+    let mut start = 0;
+    while start < keys.len() {
+        let key = keys[start].0;
+        let end = start
+            + keys[start..]
+                .iter()
+                .take_while(|&&(other, _)| other == key)
+                .count();
+        if key_ties(key) && end - start > 1 {
+            slice[start..end].sort_unstable_by(|a, b| a.suffix_bytes().cmp(b.suffix_bytes()));
+        }
+        start = end;
+    }
+}
+
+/// LSD radix sort on the 5 bytes of the keys, skipping bytes which are the same for every key
+fn radix_sort(keys: &mut Vec<(u64, usize)>) {
+    let mut scratch = vec![(0, 0); keys.len()];
+    for shift in (0..40).step_by(8) {
+        let mut counts = [0usize; 256];
+        for &(key, _) in keys.iter() {
+            counts[(key >> shift) as u8 as usize] += 1;
+        }
+        if counts.contains(&keys.len()) {
+            continue;
+        }
+
+        let mut offsets = [0usize; 256];
+        for byte in 1..256 {
+            offsets[byte] = offsets[byte - 1] + counts[byte - 1];
+        }
+        for &entry in keys.iter() {
+            let byte = (entry.0 >> shift) as u8 as usize;
+            scratch[offsets[byte]] = entry;
+            offsets[byte] += 1;
+        }
+        std::mem::swap(keys, &mut scratch);
+    }
+}
+
+/// Moves the element at order[i] to i for every i, leaving order as the identity
+fn permute(slice: &mut [UmbraArcString], order: &mut [usize]) {
+    for start in 0..order.len() {
+        let mut current = start;
+        while order[current] != current {
+            let next = order[current];
+            order[current] = current;
+            if next == start {
+                break;
+            }
+            slice.swap(current, next);
+            current = next;
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::sort_unstable;
+    use crate::arc::UmbraArcString;
+
+    /// Strings built from a few short pieces, so many share prefixes and tie on the header
+    fn strings(count: usize) -> Vec<UmbraArcString> {
+        let pieces = [
+            "",
+            "a",
+            "ab",
+            "\0",
+            "abcd",
+            "abcdefghijklmnop",
+            "é",
+            "zz",
+            "ab\0\0",
+        ];
+        let mut state: u64 = 0x2545_f491_4f6c_dd1d;
+        (0..count)
+            .map(|_| {
+                let mut val = String::new();
+                for _ in 0..3 {
+                    state ^= state << 13;
+                    state ^= state >> 7;
+                    state ^= state << 17;
+                    val.push_str(pieces[state as usize % pieces.len()]);
+                }
+                UmbraArcString::new(val)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn sort_test() {
+        for count in [0, 1, 10, 1000] {
+            let mut values = strings(count);
+            let mut expected = values.clone();
+            expected.sort();
+
+            sort_unstable(&mut values);
+            assert_eq!(values, expected);
+        }
+    }
+
+    #[test]
+    fn sorted_input_test() {
+        let mut values = strings(500);
+        values.sort();
+        let expected = values.clone();
+        sort_unstable(&mut values);
+        assert_eq!(values, expected);
+
+        values.reverse();
+        sort_unstable(&mut values);
+        assert_eq!(values, expected);
+    }
+}
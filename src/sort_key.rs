@@ -0,0 +1,269 @@
+//! Normalized sort keys, byte strings which compare with memcmp in the same order as the values they encode, so keys of
+//! several columns can be concatenated and sorted as plain bytes.
+//!
+//! A key is a marker byte placing nulls first or last, followed for a value by its bytes with each 0x00 escaped as
+//! 0x00 0xFF and a 0x00 0x00 terminator, which sorts below any escaped byte so shorter strings come first. For
+//! descending order every byte after the marker is inverted.
+
+use crate::{arc::UmbraArcString, collation::Collation, UmbraError};
+
+/// Marker of a null sorted first, below a value
+const NULL_FIRST: u8 = 0x00;
+/// Marker of a value
+const VALUE: u8 = 0x01;
+/// Marker of a null sorted last, above a value
+const NULL_LAST: u8 = 0x02;
+
+const ESCAPE: u8 = 0x00;
+const ESCAPED_ZERO: u8 = 0xFF;
+const TERMINATOR: u8 = 0x00;
+
+/// How a column is ordered in a sort key
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct SortKeyOptions {
+    pub descending: bool,
+    pub nulls_first: bool,
+}
+
+impl SortKeyOptions {
+    fn null_marker(&self) -> u8 {
+        if self.nulls_first {
+            NULL_FIRST
+        } else {
+            NULL_LAST
+        }
+    }
+
+    /// The byte written for a byte of the key, which is inverted when descending
+    fn byte(&self, byte: u8) -> u8 {
+        if self.descending {
+            !byte
+        } else {
+            byte
+        }
+    }
+}
+
+impl UmbraArcString {
+    /// Appends a sort key for this string to buf
+    pub fn write_sort_key(&self, buf: &mut Vec<u8>, opts: SortKeyOptions) {
+        buf.reserve(self.len() + 3);
+        write_value(buf, self.as_bytes(), opts);
+    }
+
+    /// Appends a sort key for this string under a collation to buf, keys compare like cmp_with
+    ///
+    /// The key holds the folded string, which is what decode_sort_key gives back.
+    pub fn write_sort_key_with(
+        &self,
+        buf: &mut Vec<u8>,
+        opts: SortKeyOptions,
+        collation: &Collation,
+    ) {
+        let mut folded = String::with_capacity(self.len());
+        folded.extend(collation.fold(self));
+        write_value(buf, folded.as_bytes(), opts);
+    }
+}
+
+fn write_value(buf: &mut Vec<u8>, bytes: &[u8], opts: SortKeyOptions) {
+    buf.push(VALUE);
+    for &byte in bytes {
+        if byte == ESCAPE {
+            buf.extend_from_slice(&[opts.byte(ESCAPE), opts.byte(ESCAPED_ZERO)]);
+        } else {
+            buf.push(opts.byte(byte));
+        }
+    }
+    buf.extend_from_slice(&[opts.byte(ESCAPE), opts.byte(TERMINATOR)]);
+}
+
+/// Appends the sort key of a null to buf
+pub fn write_null_sort_key(buf: &mut Vec<u8>, opts: SortKeyOptions) {
+    buf.push(opts.null_marker());
+}
+
+/// Appends the sort key of a string which may be null to buf
+pub fn write_optional_sort_key(
+    val: Option<&UmbraArcString>,
+    buf: &mut Vec<u8>,
+    opts: SortKeyOptions,
+) {
+    match val {
+        Some(val) => val.write_sort_key(buf, opts),
+        None => write_null_sort_key(buf, opts),
+    }
+}
+
+/// Decodes the sort key at the start of key, written with the same options, returning the string and the rest of key
+pub fn decode_sort_key(
+    key: &[u8],
+    opts: SortKeyOptions,
+) -> Result<(Option<UmbraArcString>, &[u8]), UmbraError> {
+    match key.split_first() {
+        Some((&VALUE, mut rest)) => {
+            let mut bytes = Vec::new();
+            loop {
+                match rest {
+                    [escape, next, tail @ ..] if opts.byte(*escape) == ESCAPE => {
+                        match opts.byte(*next) {
+                            TERMINATOR => {
+                                let val = String::from_utf8(bytes)
+                                    .map_err(|_| UmbraError::InvalidSortKey)?;
+                                return Ok((Some(UmbraArcString::try_new(val)?), tail));
+                            }
+                            ESCAPED_ZERO => bytes.push(0),
+                            _ => return Err(UmbraError::InvalidSortKey),
+                        }
+                        rest = tail;
+                    }
+                    [byte, tail @ ..] if opts.byte(*byte) != ESCAPE => {
+                        bytes.push(opts.byte(*byte));
+                        rest = tail;
+                    }
+                    _ => return Err(UmbraError::InvalidSortKey),
+                }
+            }
+        }
+        Some((&marker, rest)) if marker == opts.null_marker() => Ok((None, rest)),
+        _ => Err(UmbraError::InvalidSortKey),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::cmp::Ordering;
+
+    use super::{decode_sort_key, write_optional_sort_key, SortKeyOptions};
+    use crate::{arc::UmbraArcString, collation::Collation, UmbraError};
+
+    fn values() -> Vec<Option<UmbraArcString>> {
+        let mut values: Vec<_> = [
+            "",
+            "\0",
+            "a",
+            "a\0",
+            "a\0b",
+            "ab",
+            "b",
+            "a string which is too long to inline",
+            "é",
+        ]
+        .into_iter()
+        .map(|val| Some(UmbraArcString::new(val)))
+        .collect();
+        values.push(None);
+        values
+    }
+
+    fn key(val: Option<&UmbraArcString>, opts: SortKeyOptions) -> Vec<u8> {
+        let mut buf = Vec::new();
+        write_optional_sort_key(val, &mut buf, opts);
+        buf
+    }
+
+    fn expected(
+        a: Option<&UmbraArcString>,
+        b: Option<&UmbraArcString>,
+        opts: SortKeyOptions,
+    ) -> Ordering {
+        match (a, b) {
+            (Some(a), Some(b)) if opts.descending => b.cmp(a),
+            (Some(a), Some(b)) => a.cmp(b),
+            (None, None) => Ordering::Equal,
+            (None, Some(_)) if opts.nulls_first => Ordering::Less,
+            (None, Some(_)) => Ordering::Greater,
+            (Some(_), None) if opts.nulls_first => Ordering::Greater,
+            (Some(_), None) => Ordering::Less,
+        }
+    }
+
+    #[test]
+    fn order_test() {
+        let values = values();
+        for descending in [false, true] {
+            for nulls_first in [false, true] {
+                let opts = SortKeyOptions {
+                    descending,
+                    nulls_first,
+                };
+                for a in &values {
+                    for b in &values {
+                        let ordering = key(a.as_ref(), opts).cmp(&key(b.as_ref(), opts));
+                        assert_eq!(
+                            ordering,
+                            expected(a.as_ref(), b.as_ref(), opts),
+                            "{a:?} {b:?} {opts:?}"
+                        );
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn decode_test() {
+        let desc = SortKeyOptions {
+            descending: true,
+            nulls_first: false,
+        };
+        let mut buf = Vec::new();
+        for val in values() {
+            write_optional_sort_key(val.as_ref(), &mut buf, SortKeyOptions::default());
+            write_optional_sort_key(val.as_ref(), &mut buf, desc);
+        }
+
+        let mut rest = buf.as_slice();
+        for val in values() {
+            let (asc_val, tail) = decode_sort_key(rest, SortKeyOptions::default()).unwrap();
+            let (desc_val, tail) = decode_sort_key(tail, desc).unwrap();
+            assert_eq!(asc_val, val);
+            assert_eq!(desc_val, val);
+            rest = tail;
+        }
+        assert!(rest.is_empty());
+    }
+
+    #[test]
+    fn collation_test() {
+        let collation = Collation::CASE_INSENSITIVE;
+        let opts = SortKeyOptions::default();
+        let values = [
+            "Zebra",
+            "apple",
+            "APPLE",
+            "Äpfel",
+            "a string which is too long to inline",
+        ]
+        .map(UmbraArcString::new);
+
+        for a in &values {
+            for b in &values {
+                let (mut a_key, mut b_key) = (Vec::new(), Vec::new());
+                a.write_sort_key_with(&mut a_key, opts, &collation);
+                b.write_sort_key_with(&mut b_key, opts, &collation);
+                assert_eq!(a_key.cmp(&b_key), a.cmp_with(b, &collation), "{a:?} {b:?}");
+            }
+        }
+
+        let mut key = Vec::new();
+        values[2].write_sort_key_with(&mut key, opts, &collation);
+        assert_eq!(decode_sort_key(&key, opts).unwrap().0.unwrap(), "apple");
+    }
+
+    #[test]
+    fn invalid_key_test() {
+        let mut buf = Vec::new();
+        UmbraArcString::new("truncated").write_sort_key(&mut buf, SortKeyOptions::default());
+        buf.pop();
+
+        assert_eq!(
+            decode_sort_key(&buf, SortKeyOptions::default()).unwrap_err(),
+            UmbraError::InvalidSortKey
+        );
+        assert_eq!(
+            decode_sort_key(&[0x07], SortKeyOptions::default()).unwrap_err(),
+            UmbraError::InvalidSortKey
+        );
+    }
+}
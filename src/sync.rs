@@ -0,0 +1,18 @@
+//! The atomics the refcounts and AtomicUmbraString are built on. Under `--cfg loom` they are loom's, so its model
+//! tests can explore the interleavings of clones and drops on different threads, which only works inside `loom::model`.
+//! AtomicUmbraString, and with it AtomicIsize and yield_now, only exists on 64 bit targets.
+
+#[cfg(loom)]
+#[cfg_attr(not(target_pointer_width = "64"), allow(unused_imports))]
+pub(crate) use loom::{
+    hint::spin_loop,
+    sync::atomic::{fence, AtomicIsize, AtomicU32, AtomicUsize},
+    thread::yield_now,
+};
+#[cfg(not(loom))]
+#[cfg_attr(not(target_pointer_width = "64"), allow(unused_imports))]
+pub(crate) use std::{
+    hint::spin_loop,
+    sync::atomic::{fence, AtomicIsize, AtomicU32, AtomicUsize},
+    thread::yield_now,
+};
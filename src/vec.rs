@@ -0,0 +1,208 @@
+//! A column of Umbra strings. The 16 byte headers are stored contiguously, and the bytes of long strings are appended
+//! to a few large buffers owned by the column, rather than each getting an allocation of its own.
+
+use std::{
+    alloc::Layout,
+    iter::Copied,
+    ops::{Bound, RangeBounds},
+    slice,
+    sync::Arc,
+};
+
+use crate::{
+    arc::{UmbraArcString, MAX_INLINE},
+    borrowed::UmbraStr,
+    common, UmbraError,
+};
+
+/// A vector of Umbra strings whose out-of-line bytes live in shared append-only buffers
+///
+/// Clones and slices copy the headers but share the buffers.
+#[derive(Debug, Clone, Default)]
+pub struct UmbraStringVec {
+    /// Headers pointing into buffers, they are only ever handed out borrowed from self so never outlive the buffers
+    headers: Vec<UmbraStr<'static>>,
+    /// Data buffers, which are never grown past their capacity so the bytes never move
+    buffers: Vec<Arc<Vec<u8>>>,
+}
+
+impl UmbraStringVec {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_capacity(capacity: usize) -> Self {
+        Self {
+            headers: Vec::with_capacity(capacity),
+            buffers: Vec::new(),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.headers.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.headers.is_empty()
+    }
+
+    /// Panics if the string is longer than u32::MAX bytes
+    pub fn push(&mut self, val: impl AsRef<str>) {
+        common::unwrap_new(self.try_push(val))
+    }
+
+    pub fn try_push(&mut self, val: impl AsRef<str>) -> Result<(), UmbraError> {
+        let bytes = val.as_ref().as_bytes();
+        let len = common::checked_len(bytes.len())?;
+
+        let header = if len as usize <= MAX_INLINE {
+            let (prefix, data) = common::split_inline(bytes);
+            // SAFETY: len is at most MAX_INLINE, and the parts start with the bytes of a str
+            unsafe { UmbraStr::from_inline_parts(len, prefix, data) }
+        } else {
+            let ptr = self.append(bytes)?;
+            // SAFETY: ptr points to a copy of the str in a buffer which is kept alive by self and never moves
+            unsafe { UmbraStr::from_ptr_parts(len, common::heap_prefix(bytes), ptr) }
+        };
+        self.headers.push(header);
+        Ok(())
+    }
+
+    /// Copies bytes to the end of the last buffer, starting a new one if it is full or shared with another vector
+    fn append(&mut self, bytes: &[u8]) -> Result<*const u8, UmbraError> {
+        let has_room = match self.buffers.last_mut().and_then(Arc::get_mut) {
+            Some(buffer) => buffer.capacity() - buffer.len() >= bytes.len(),
+            None => false,
+        };
+        if !has_room {
+            let capacity = common::next_buffer_size(self.buffers.len(), bytes.len());
+            let mut buffer = Vec::new();
+            buffer
+                .try_reserve_exact(capacity)
+                .map_err(|_| UmbraError::AllocError {
+                    layout: Layout::array::<u8>(capacity).expect("buffer too large"),
+                })?;
+            self.buffers.push(Arc::new(buffer));
+        }
+
+        let buffer = self
+            .buffers
+            .last_mut()
+            .and_then(Arc::get_mut)
+            .expect("the last buffer is unique");
+        let start = buffer.len();
+        // there is room for the bytes, so this doesn't reallocate and move the bytes other headers point to
+        buffer.extend_from_slice(bytes);
+        // SAFETY: start is within the buffer, and as_ptr doesn't invalidate pointers taken from it before
+        Ok(unsafe { buffer.as_ptr().add(start) })
+    }
+
+    pub fn get(&self, index: usize) -> Option<UmbraStr<'_>> {
+        self.headers.get(index).copied()
+    }
+
+    pub fn as_slice(&self) -> &[UmbraStr<'_>] {
+        &self.headers
+    }
+
+    pub fn iter(&self) -> Copied<slice::Iter<'_, UmbraStr<'_>>> {
+        self.as_slice().iter().copied()
+    }
+
+    /// A vector of some of the strings, which shares the buffers rather than copying them
+    ///
+    /// Panics if the range is out of bounds
+    pub fn slice(&self, range: impl RangeBounds<usize>) -> UmbraStringVec {
+        let range: (Bound<usize>, Bound<usize>) =
+            (range.start_bound().cloned(), range.end_bound().cloned());
+        UmbraStringVec {
+            headers: self.headers[range].to_vec(),
+            buffers: self.buffers.clone(),
+        }
+    }
+
+    /// Copies a string out of the shared buffers, this only allocates if the string isn't inline
+    ///
+    /// Panics if index is out of bounds
+    pub fn to_owned_string(&self, index: usize) -> UmbraArcString {
+        self.headers[index].to_owned()
+    }
+}
+
+impl<S: AsRef<str>> FromIterator<S> for UmbraStringVec {
+    fn from_iter<T: IntoIterator<Item = S>>(iter: T) -> Self {
+        let mut vec = UmbraStringVec::new();
+        vec.extend(iter);
+        vec
+    }
+}
+
+impl<S: AsRef<str>> Extend<S> for UmbraStringVec {
+    fn extend<T: IntoIterator<Item = S>>(&mut self, iter: T) {
+        for val in iter {
+            self.push(val);
+        }
+    }
+}
+
+impl<'a> IntoIterator for &'a UmbraStringVec {
+    type Item = UmbraStr<'a>;
+    type IntoIter = Copied<slice::Iter<'a, UmbraStr<'a>>>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::UmbraStringVec;
+
+    const LONG: &str = "a string which is too long to inline";
+
+    #[test]
+    fn push_get_test() {
+        let mut vec = UmbraStringVec::new();
+        vec.push("short");
+        vec.push(LONG);
+        vec.push("");
+
+        assert_eq!(vec.len(), 3);
+        assert_eq!(vec.get(0).unwrap(), "short");
+        assert_eq!(vec.get(1).unwrap(), LONG);
+        assert!(vec.get(3).is_none());
+        assert_eq!(vec.iter().map(|val| val.len()).sum::<usize>(), 41);
+        assert_eq!(vec.to_owned_string(1), LONG);
+    }
+
+    #[test]
+    fn shared_buffer_test() {
+        let vec: UmbraStringVec = [LONG].into_iter().collect();
+        assert!(vec.buffers[0].capacity() < 16 * 1024);
+
+        // buffers double from 8 KiB, so 420 KB of strings take six of them
+        let vec: UmbraStringVec = (0..10_000).map(|i| format!("{LONG} {i}")).collect();
+        assert_eq!(vec.buffers.len(), 6);
+        assert_eq!(vec.get(9_999).unwrap(), &*format!("{LONG} 9999"));
+
+        let huge = "x".repeat(3 * 1024 * 1024);
+        let vec: UmbraStringVec = [LONG, &huge, LONG].into_iter().collect();
+        assert_eq!(vec.buffers.len(), 3);
+        assert_eq!(vec.get(1).unwrap(), &*huge);
+        assert!(vec.buffers[2].capacity() < 2 * 1024 * 1024);
+    }
+
+    #[test]
+    fn slice_test() {
+        let mut vec: UmbraStringVec = ["short", LONG, "another string too long to inline"]
+            .into_iter()
+            .collect();
+        let slice = vec.slice(1..);
+        vec.push("pushed after slicing, so it can't share the buffer");
+
+        assert_eq!(slice.iter().collect::<Vec<_>>(), vec.as_slice()[1..3]);
+        assert_eq!(vec.buffers.len(), 2);
+        drop(vec);
+        assert_eq!(slice.to_owned_string(0), LONG);
+    }
+}